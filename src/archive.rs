@@ -0,0 +1,285 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::registry::Registry;
+use digest::Digest;
+use flate2::read::GzDecoder;
+use serde_derive::Serialize;
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+/// A `.crate` file (gzipped tarball) stored in a registry
+///
+/// All paths accepted and returned by this type are relative to the `{name}-{version}`
+/// directory inside the tarball, and use `/` as separator.
+///
+#[derive(Debug, Clone)]
+pub struct CrateArchive {
+    name: String,
+    version: String,
+    content: Arc<Vec<u8>>,
+}
+
+/// A node in the source tree of a crate
+#[derive(Debug)]
+pub enum SourceNode {
+    File(SourceFile),
+    /// A directory and its direct children, sorted by name
+    Dir(Vec<SourceEntry>),
+}
+
+/// A regular file in the source tree of a crate
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub size: u64,
+    /// `None` if the file is larger than the size limit
+    pub content: Option<Vec<u8>>,
+    /// SHA-256 of a file larger than the size limit, if it is compared with others
+    pub sha256: Option<Vec<u8>>,
+}
+
+/// A directory entry in the source tree of a crate
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SourceEntry {
+    pub name: String,
+    pub kind: SourceKind,
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    File,
+    Dir,
+}
+
+impl CrateArchive {
+    /// Open the `.crate` file of `name`-`version`, returns `None` if it is not stored
+    ///
+    /// The name is matched case-insensitively, the archive has the published name.
+    ///
+    pub fn open(registry: &Registry, name: &str, version: &str) -> SkrdResult<Option<Self>> {
+        let meta = match registry.find_crate(name, version)? {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        let content = match registry.storage().read(&registry.crate_path(&meta))? {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+
+        Ok(Some(CrateArchive {
+            name: meta.name,
+            version: meta.version,
+            content: Arc::new(content),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Look up `path` in the source tree, `""` is the root directory
+    ///
+    /// Files larger than `limit` bytes are returned without content.
+    ///
+    pub fn lookup(&self, path: &str, limit: u64) -> SkrdResult<Option<SourceNode>> {
+        let target = normalize_source_path(path)?;
+        let mut children: BTreeMap<String, SourceEntry> = BTreeMap::new();
+        let mut is_dir = target.is_empty();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(&self.content[..]));
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = match self.strip_root(&entry.path()?) {
+                Some(p) => p,
+                None => continue,
+            };
+            let entry_is_dir = entry.header().entry_type().is_dir();
+
+            if entry_path == target {
+                if entry_is_dir {
+                    is_dir = true;
+                    continue;
+                }
+
+                let size = entry.header().size()?;
+                if size > limit {
+                    return Ok(Some(SourceNode::File(SourceFile {
+                        size,
+                        content: None,
+                        sha256: None,
+                    })));
+                }
+
+                let mut content = Vec::with_capacity(size as usize);
+                entry.read_to_end(&mut content)?;
+                return Ok(Some(SourceNode::File(SourceFile {
+                    size,
+                    content: Some(content),
+                    sha256: None,
+                })));
+            }
+
+            let rest = if target.is_empty() {
+                entry_path.as_str()
+            } else if entry_path.starts_with(&target) && entry_path[target.len()..].starts_with('/')
+            {
+                &entry_path[target.len() + 1..]
+            } else {
+                continue;
+            };
+
+            is_dir = true;
+            if rest.is_empty() {
+                continue;
+            }
+
+            let (child, kind, size) = match rest.find('/') {
+                Some(i) => (&rest[..i], SourceKind::Dir, None),
+                None if entry_is_dir => (rest, SourceKind::Dir, None),
+                None => (rest, SourceKind::File, Some(entry.header().size()?)),
+            };
+
+            children
+                .entry(child.to_owned())
+                .or_insert_with(|| SourceEntry {
+                    name: child.to_owned(),
+                    kind,
+                    size,
+                });
+        }
+
+        if is_dir {
+            Ok(Some(SourceNode::Dir(children.into_values().collect())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Sizes of all regular files in the source tree, sorted by path, no content is read
+    ///
+    pub fn file_sizes(&self) -> SkrdResult<BTreeMap<String, u64>> {
+        let mut sizes = BTreeMap::new();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(&self.content[..]));
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            if let Some(entry_path) = self.strip_root(&entry.path()?) {
+                sizes.insert(entry_path, entry.header().size()?);
+            }
+        }
+
+        Ok(sizes)
+    }
+
+    /// Read the regular files of `paths` in the source tree, sorted by path
+    ///
+    /// Files larger than `limit` bytes are returned without content.
+    ///
+    pub fn files(
+        &self,
+        paths: &BTreeSet<String>,
+        limit: u64,
+    ) -> SkrdResult<BTreeMap<String, SourceFile>> {
+        let mut files = BTreeMap::new();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(&self.content[..]));
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let entry_path = match self.strip_root(&entry.path()?) {
+                Some(p) if paths.contains(&p) => p,
+                _ => continue,
+            };
+
+            let size = entry.header().size()?;
+            let file = if size > limit {
+                // large files are only hashed, so identical ones are still found
+                let mut sha256 = Sha256::new();
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    let len = entry.read(&mut buf)?;
+                    if len == 0 {
+                        break;
+                    }
+                    sha256.input(&buf[..len]);
+                }
+                SourceFile {
+                    size,
+                    content: None,
+                    sha256: Some(sha256.result().to_vec()),
+                }
+            } else {
+                let mut content = Vec::with_capacity(size as usize);
+                entry.read_to_end(&mut content)?;
+                SourceFile {
+                    size,
+                    content: Some(content),
+                    sha256: None,
+                }
+            };
+
+            files.insert(entry_path, file);
+        }
+
+        Ok(files)
+    }
+
+    /// Strip the `{name}-{version}/` directory of a path in the tarball
+    ///
+    fn strip_root(&self, path: &Path) -> Option<String> {
+        let root = format!("{}-{}", self.name, self.version);
+        let mut components = path.components();
+        match components.next() {
+            Some(Component::Normal(first)) if first == root.as_str() => {}
+            _ => return None,
+        }
+
+        let mut stripped = String::new();
+        for component in components {
+            match component {
+                Component::Normal(c) => {
+                    if !stripped.is_empty() {
+                        stripped.push('/');
+                    }
+                    stripped.push_str(c.to_str()?);
+                }
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+
+        Some(stripped)
+    }
+}
+
+/// Normalize a source path from url, rejects `..` and absolute paths
+///
+pub fn normalize_source_path(path: &str) -> SkrdResult<String> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return Err(SkrdError::Custom(format!("invalid source path: {}", path))),
+            _ => parts.push(part),
+        }
+    }
+
+    Ok(parts.join("/"))
+}
@@ -0,0 +1,422 @@
+use crate::bundle::{bundle_path, file_sum, ManifestEntry};
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::{check_index, index_files, read_index_file, verify_crates, CrateReport};
+use crate::registry::Registry;
+use crate::util::migrate_legacy_crate_files;
+use digest::Digest;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs::create_dir_all;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Version of the backup format
+pub const BACKUP_FORMAT: u32 = 1;
+pub const OBJECTS_DIRECTORY: &str = "objects";
+pub const SNAPSHOTS_DIRECTORY: &str = "snapshots";
+
+/// Snapshot of a registry in a backup directory
+///
+/// Directory structure:
+///
+/// backup
+///   ├─objects
+///   │  └─{sha256[0..2]}
+///   │     └─{sha256}
+///   └─snapshots
+///      └─{id}.json
+///
+/// Files are stored in `objects` once by their SHA-256, so a backup only copies the files
+/// which are not in the previous snapshots. A snapshot lists the files in the registry
+/// root except `crates` and `registry.lock`, and the crate files in the storage.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub format: u32,
+    pub id: String,
+    pub registry: String,
+    pub created: String,
+    /// Commit id of the index `HEAD` when the snapshot is taken
+    pub index_head: Option<String>,
+    /// Directories relative to the registry root, git needs empty ones like `objects/pack`
+    pub dirs: Vec<String>,
+    /// Files relative to the registry root
+    pub files: Vec<ManifestEntry>,
+    /// Crate files relative to the storage, their SHA-256 are the checksums in the index
+    pub crates: Vec<ManifestEntry>,
+}
+
+impl Snapshot {
+    /// Open snapshot `id` of the backup at `backup`, or the latest one if `id` is `None`
+    ///
+    pub fn open(backup: &Path, id: Option<&str>) -> SkrdResult<Self> {
+        let ids = snapshot_ids(backup)?;
+        let id = match id {
+            Some(id) if ids.iter().any(|i| i == id) => id,
+            Some(id) => {
+                return Err(SkrdError::Custom(format!(
+                    "snapshot {} is not found in {}, snapshots: {}",
+                    id,
+                    backup.display(),
+                    ids.join(", ")
+                )))
+            }
+            None => ids.last().ok_or_else(|| {
+                SkrdError::Custom(format!("no snapshot is found in {}", backup.display()))
+            })?,
+        };
+
+        let path = snapshot_path(backup, id);
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.iter().chain(&self.crates).map(|f| f.size).sum()
+    }
+}
+
+/// Ids of the snapshots in a backup, the latest one is the last
+///
+pub fn snapshot_ids(backup: &Path) -> SkrdResult<Vec<String>> {
+    let dir = backup.join(SNAPSHOTS_DIRECTORY);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut ids = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+                ids.push(id.to_owned());
+            }
+        }
+    }
+    // ids are timestamps
+    ids.sort();
+    Ok(ids)
+}
+
+fn snapshot_path(backup: &Path, id: &str) -> PathBuf {
+    backup
+        .join(SNAPSHOTS_DIRECTORY)
+        .join(format!("{}.json", id))
+}
+
+fn object_path(backup: &Path, sha256: &[u8]) -> PathBuf {
+    let hex = hex::encode(sha256);
+    backup.join(OBJECTS_DIRECTORY).join(&hex[..2]).join(hex)
+}
+
+/// Result of `backup`
+#[derive(Debug, Default)]
+pub struct BackupReport {
+    /// Objects copied into the backup
+    pub stored: usize,
+    pub bytes: u64,
+    /// Files already in the backup
+    pub existing: usize,
+    /// Versions in the index without crate files
+    pub missing: Vec<String>,
+    /// Crate files of which the checksum does not match the index, they are not backed up
+    pub corrupted: Vec<String>,
+}
+
+impl std::ops::Add for BackupReport {
+    type Output = BackupReport;
+
+    fn add(mut self, other: BackupReport) -> BackupReport {
+        self.stored += other.stored;
+        self.bytes += other.bytes;
+        self.existing += other.existing;
+        self.missing.extend(other.missing);
+        self.corrupted.extend(other.corrupted);
+        self
+    }
+}
+
+/// Take a snapshot of `registry` into the backup directory `backup`
+///
+/// The registry should be locked, so the index and crates are consistent. The snapshot
+/// file is written last, an interrupted backup leaves only unreferenced objects.
+///
+pub fn backup(registry: &Registry, backup: &Path) -> SkrdResult<(Snapshot, BackupReport)> {
+    // crate files are read at the paths of the registry layout
+    migrate_legacy_crate_files(registry)?;
+
+    let id = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let path = snapshot_path(backup, &id);
+    if path.exists() {
+        return Err(SkrdError::Custom(format!(
+            "snapshot {} already exists",
+            path.display()
+        )));
+    }
+
+    let mut report = BackupReport::default();
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+
+    let lock_path = registry.root().join(Registry::LOCK_FILE);
+    let walker = WalkDir::new(registry.root())
+        .min_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| entry.path() != registry.crates_path() && entry.path() != lock_path);
+    for entry in walker {
+        let entry = entry?;
+        let name = bundle_path(registry.root(), entry.path())?;
+        if entry.file_type().is_dir() {
+            dirs.push(name);
+        } else if entry.file_type().is_file() {
+            let sha256 = file_sum(entry.path())?;
+            let size = entry.metadata()?.len();
+            let object = object_path(backup, &sha256);
+            if object.is_file() {
+                report.existing += 1;
+            } else {
+                write_object(&object, &std::fs::read(entry.path())?)?;
+                report.stored += 1;
+                report.bytes += size;
+            }
+            files.push(ManifestEntry {
+                path: name,
+                size,
+                sha256,
+            });
+        }
+    }
+
+    // a snapshot is not written if any crate file fails to be backed up
+    let mut results: Vec<(usize, Vec<ManifestEntry>, BackupReport)> = index_files(registry)
+        .enumerate()
+        .par_bridge()
+        .map(|(i, path)| {
+            backup_crates(registry, backup, &path).map(|(crates, report)| (i, crates, report))
+        })
+        .collect::<SkrdResult<_>>()?;
+    results.sort_by_key(|(i, _, _)| *i);
+
+    let mut crates = Vec::new();
+    for (_, entries, result) in results {
+        crates.extend(entries);
+        report = report + result;
+    }
+
+    let index_head = git2::Repository::open(registry.index_path())
+        .and_then(|repo| Ok(repo.head()?.peel_to_commit()?.id().to_string()))
+        .ok();
+
+    let snapshot = Snapshot {
+        format: BACKUP_FORMAT,
+        id,
+        registry: registry.config().name().to_owned(),
+        created: chrono::Local::now().to_rfc3339(),
+        index_head,
+        dirs,
+        files,
+        crates,
+    };
+
+    create_dir_all(backup.join(SNAPSHOTS_DIRECTORY))?;
+    write_atomic(&path, &serde_json::to_vec_pretty(&snapshot)?)?;
+
+    Ok((snapshot, report))
+}
+
+/// Back up the crate files of the versions in an index file
+///
+fn backup_crates(
+    registry: &Registry,
+    backup: &Path,
+    index_file: &Path,
+) -> SkrdResult<(Vec<ManifestEntry>, BackupReport)> {
+    let mut report = BackupReport::default();
+    let mut crates = Vec::new();
+
+    for meta in read_index_file(index_file)? {
+        let path = registry.crate_path(&meta);
+        let object = object_path(backup, &meta.checksum);
+
+        // crate files never change, an object of the checksum is the same file
+        let size = if object.is_file() {
+            report.existing += 1;
+            object.metadata()?.len()
+        } else {
+            let content = match registry.storage().read(&path)? {
+                Some(content) => content,
+                None => {
+                    report.missing.push(meta.to_string());
+                    continue;
+                }
+            };
+            if Sha256::digest(&content)[..] != meta.checksum {
+                warn!("Crate file {} is corrupted, skipped.", path);
+                report.corrupted.push(path);
+                continue;
+            }
+
+            write_object(&object, &content)?;
+            report.stored += 1;
+            report.bytes += content.len() as u64;
+            content.len() as u64
+        };
+
+        crates.push(ManifestEntry {
+            path,
+            size,
+            sha256: meta.checksum,
+        });
+    }
+
+    Ok((crates, report))
+}
+
+fn write_object(object: &Path, content: &[u8]) -> SkrdResult<()> {
+    if let Some(parent) = object.parent() {
+        create_dir_all(parent)?;
+    }
+    write_atomic(object, content)
+}
+
+/// Write a file through a temporary one, so readers never see a partially written file
+///
+fn write_atomic(path: &Path, content: &[u8]) -> SkrdResult<()> {
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&temp, content)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Result of `restore`
+#[derive(Debug)]
+pub struct RestoreReport {
+    pub files: usize,
+    pub crates: usize,
+    /// Problems of the restored index
+    pub index_issues: usize,
+    /// Crate files checked against the checksums in the restored index
+    pub verified: CrateReport,
+}
+
+/// Restore a snapshot in the backup directory `backup` as a new registry at `root`
+///
+/// Every object is checked against its SHA-256 before it is restored, then the crate files
+/// are verified against the checksums in the restored index.
+///
+pub fn restore(backup: &Path, snapshot: &Snapshot, root: &Path) -> SkrdResult<RestoreReport> {
+    if root.exists() {
+        return Err(SkrdError::Custom(format!(
+            "{} already exists, a snapshot can only be restored as a new registry",
+            root.display()
+        )));
+    }
+
+    let mut staging = root.as_os_str().to_owned();
+    staging.push(".restore");
+    let staging = PathBuf::from(staging);
+    if staging.exists() {
+        return Err(SkrdError::Custom(format!(
+            "{} already exists, remove it if no other restore is running",
+            staging.display()
+        )));
+    }
+
+    // the registry is only moved to `root` after all files are restored and its index is
+    // the one of the snapshot
+    let result = restore_files(backup, snapshot, &staging)
+        .and_then(|_| check_index_head(&staging, snapshot))
+        .and_then(|_| Ok(std::fs::rename(&staging, root)?));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    result?;
+
+    let registry = Registry::open(root)?;
+    info!(
+        "{} files and {} crate files are restored.",
+        snapshot.files.len(),
+        snapshot.crates.len()
+    );
+
+    Ok(RestoreReport {
+        files: snapshot.files.len(),
+        crates: snapshot.crates.len(),
+        index_issues: check_index(&registry)?.issues.len(),
+        verified: verify_crates(&registry),
+    })
+}
+
+fn restore_files(backup: &Path, snapshot: &Snapshot, staging: &Path) -> SkrdResult<()> {
+    create_dir_all(staging)?;
+    for dir in &snapshot.dirs {
+        create_dir_all(staging.join(checked_path(dir)?))?;
+    }
+
+    for entry in &snapshot.files {
+        let target = staging.join(checked_path(&entry.path)?);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        std::fs::write(target, read_object(backup, entry)?)?;
+    }
+
+    // crate files are written by the storage configured in the restored `registry.toml`
+    let registry = Registry::open(staging)?;
+    for entry in &snapshot.crates {
+        let content = read_object(backup, entry)?;
+        registry.storage().write(&entry.path, &content)?;
+    }
+
+    Ok(())
+}
+
+/// Check that the index HEAD of the registry restored at `staging` is the one of `snapshot`
+///
+fn check_index_head(staging: &Path, snapshot: &Snapshot) -> SkrdResult<()> {
+    let index_head = git2::Repository::open(staging.join(Registry::INDEX_DIRECTORY))?
+        .head()?
+        .peel_to_commit()?
+        .id()
+        .to_string();
+    if snapshot.index_head.as_ref() != Some(&index_head) {
+        return Err(SkrdError::Custom(format!(
+            "index HEAD {} is not the one of the snapshot",
+            index_head
+        )));
+    }
+    Ok(())
+}
+
+/// Read the object of a file in a snapshot, and check it against the SHA-256 of the file
+///
+fn read_object(backup: &Path, entry: &ManifestEntry) -> SkrdResult<Vec<u8>> {
+    let object = object_path(backup, &entry.sha256);
+    let content = std::fs::read(&object)
+        .map_err(|e| SkrdError::Custom(format!("object of {} read error: {}", entry.path, e)))?;
+    if Sha256::digest(&content)[..] != entry.sha256 {
+        return Err(SkrdError::Custom(format!(
+            "object {} of {} is corrupted",
+            object.display(),
+            entry.path
+        )));
+    }
+    Ok(content)
+}
+
+/// Path of a snapshot entry, only normal components are allowed
+///
+fn checked_path(path: &str) -> SkrdResult<&Path> {
+    let checked = Path::new(path);
+    let normal = checked
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !normal || path.is_empty() {
+        return Err(SkrdError::Custom(format!(
+            "invalid path in snapshot: {}",
+            path
+        )));
+    }
+    Ok(checked)
+}
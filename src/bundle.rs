@@ -0,0 +1,1033 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::{index_files, read_index_file};
+use crate::registry::{CrateMeta, Registry, RegistryConfig};
+use crate::storage::{require_local, Layout};
+use crate::util::{migrate_legacy_crate_files, write_config_json};
+use digest::Digest;
+use git2::Oid;
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command as PsCommand;
+use walkdir::WalkDir;
+
+/// Version of the bundle format
+pub const BUNDLE_FORMAT: u32 = 1;
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const INDEX_BUNDLE_FILE: &str = "index.bundle";
+
+/// Bundle commit fetched into the index when a bundle is imported
+const BUNDLE_REF: &str = "refs/silkroad/bundle";
+
+/// Index commit of the last imported bundle, `config.json` of the registry is committed on top of it
+const UNPACKAGED_REF: &str = "refs/silkroad/unpackaged";
+
+/// Manifest of a bundle
+///
+/// A bundle is a tar archive of a registry, which may be split into several volumes.
+///
+/// Archive structure:
+///
+/// ├─registry.toml
+/// ├─index
+/// │  ├─.git
+/// │  └─ ...
+/// ├─crates
+/// │  └─ ...
+/// └─manifest.json
+///
+/// A delta bundle created with a base revision contains `index.bundle` (a git bundle of
+/// the index commits after the base) instead of `index`, and only the crates added after it.
+///
+/// `manifest.json` is the last entry, it lists SHA-256 sums of all the other files.
+/// SHA-256 sums of the volumes are written to `{bundle}.sha256` next to them.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub format: u32,
+    pub registry: String,
+    pub created: String,
+    /// Commit id of the index `HEAD` when the bundle is created, also used as the bundle id
+    pub index_head: Option<String>,
+    /// Base commit id of a delta bundle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    #[serde(with = "hex_serde")]
+    pub sha256: [u8; 32],
+}
+
+/// Pack up `registry.toml`, the index repository and crates of a registry into a bundle
+///
+/// All crates are packed if `crates` is empty, otherwise only the crates named in it.
+/// If `since` is a revision of the index, a delta bundle is created.
+/// Returns the manifest and paths of the volumes.
+///
+pub fn create_bundle(
+    registry: &Registry,
+    output: &Path,
+    volume_size: Option<u64>,
+    crates: &[String],
+    since: Option<&str>,
+) -> SkrdResult<(Manifest, Vec<PathBuf>)> {
+    // crate files are packed from the `crates` directory, at the paths of the registry layout
+    require_local(registry.storage(), "package")?;
+    migrate_legacy_crate_files(registry)?;
+
+    let repo = git2::Repository::open(registry.index_path())?;
+    let head = repo.head()?.peel_to_commit()?;
+    let base = match since {
+        Some(since) => {
+            let base = repo.revparse_single(since)?.peel_to_commit()?;
+            if base.id() == head.id() {
+                return Err(SkrdError::Custom(format!(
+                    "index has no commits after {}",
+                    since
+                )));
+            }
+            if !repo.graph_descendant_of(head.id(), base.id())? {
+                return Err(SkrdError::Custom(format!(
+                    "{} is not an ancestor of the index HEAD",
+                    since
+                )));
+            }
+            Some(base)
+        }
+        None => None,
+    };
+
+    let mut builder = tar::Builder::new(VolumeWriter::new(output, volume_size));
+    let mut files = Vec::new();
+
+    append_file(
+        &mut builder,
+        &registry.root().join(Registry::REGISTRY_TOML_FILE),
+        Registry::REGISTRY_TOML_FILE,
+        &mut files,
+    )?;
+
+    match &base {
+        Some(base) => {
+            // `git bundle` runs in the index directory
+            let mut index_bundle = std::env::current_dir()?.join(output).into_os_string();
+            index_bundle.push(".index.tmp");
+            let index_bundle = PathBuf::from(index_bundle);
+
+            let result = create_index_bundle(registry, base.id(), &index_bundle).and_then(|_| {
+                append_file(&mut builder, &index_bundle, INDEX_BUNDLE_FILE, &mut files)
+            });
+            if index_bundle.exists() {
+                std::fs::remove_file(&index_bundle)?;
+            }
+            result?;
+            info!("Index commits after {} are packed.", base.id());
+
+            for meta in added_crates(&repo, base, &head)? {
+                if !is_selected(&meta.name, crates) {
+                    continue;
+                }
+                let path = registry.crates_path().join(registry.crate_path(&meta));
+                if !path.is_file() {
+                    warn!("Crate file {} is not downloaded, skipped.", path.display());
+                    continue;
+                }
+                let name = bundle_path(registry.root(), &path)?;
+                append_file(&mut builder, &path, &name, &mut files)?;
+            }
+            info!("Crates added after {} are packed.", base.id());
+        }
+        None => {
+            for entry in
+                WalkDir::new(registry.index_path()).sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            {
+                let entry = entry?;
+                let name = bundle_path(registry.root(), entry.path())?;
+
+                // git needs empty directories like `objects/pack`
+                if entry.file_type().is_dir() {
+                    builder.append_dir(&name, entry.path())?;
+                } else if entry.file_type().is_file() {
+                    append_file(&mut builder, entry.path(), &name, &mut files)?;
+                }
+            }
+            info!("Index is packed.");
+
+            for index_file in index_files(registry) {
+                for meta in read_index_file(&index_file)? {
+                    if !is_selected(&meta.name, crates) {
+                        continue;
+                    }
+                    // versions not downloaded yet are not packed
+                    let path = registry.crates_path().join(registry.crate_path(&meta));
+                    if !path.is_file() {
+                        continue;
+                    }
+
+                    let name = bundle_path(registry.root(), &path)?;
+                    append_file(&mut builder, &path, &name, &mut files)?;
+                }
+            }
+            info!("Crates are packed.");
+        }
+    }
+
+    let manifest = Manifest {
+        format: BUNDLE_FORMAT,
+        registry: registry.config().name().to_owned(),
+        created: chrono::Local::now().to_rfc3339(),
+        index_head: Some(head.id().to_string()),
+        base: base.map(|base| base.id().to_string()),
+        files,
+    };
+
+    let content = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, MANIFEST_FILE, content.as_slice())?;
+
+    let volumes = builder.into_inner()?.finish()?;
+
+    Ok((manifest, volumes))
+}
+
+/// Create a git bundle of the index commits after `base`
+///
+fn create_index_bundle(registry: &Registry, base: Oid, path: &Path) -> SkrdResult<()> {
+    // TODO: no dependency on `git`
+    let output = PsCommand::new("git")
+        .arg("bundle")
+        .arg("create")
+        .arg(path)
+        .arg(format!("{}..HEAD", base))
+        .current_dir(registry.index_path())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(SkrdError::Custom(format!(
+            "git bundle error: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Get versions added to the index between two commits
+///
+fn added_crates(
+    repo: &git2::Repository,
+    base: &git2::Commit,
+    head: &git2::Commit,
+) -> SkrdResult<Vec<CrateMeta>> {
+    let base_tree = base.tree()?;
+    let head_tree = head.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None)?;
+
+    let mut added = Vec::new();
+    for delta in diff.deltas() {
+        let file_path = match delta.new_file().path() {
+            Some(path) => path,
+            None => continue,
+        };
+        if file_path == Path::new(Registry::CONFIG_JSON_FILE) {
+            continue;
+        }
+
+        let old_versions: BTreeSet<String> = read_blob_versions(repo, delta.old_file().id())
+            .into_iter()
+            .map(|meta| meta.version)
+            .collect();
+
+        for meta in read_blob_versions(repo, delta.new_file().id()) {
+            if !old_versions.contains(&meta.version) {
+                added.push(meta);
+            }
+        }
+    }
+
+    Ok(added)
+}
+
+fn read_blob_versions(repo: &git2::Repository, id: Oid) -> Vec<CrateMeta> {
+    if id.is_zero() {
+        return Vec::new();
+    }
+
+    match repo.find_blob(id) {
+        Ok(blob) => String::from_utf8_lossy(blob.content())
+            .lines()
+            .filter_map(|line| CrateMeta::from_line(line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn append_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &str,
+    files: &mut Vec<ManifestEntry>,
+) -> SkrdResult<()> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(&metadata);
+
+    let mut reader = HashReader::new(file);
+    builder.append_data(&mut header, name, &mut reader)?;
+
+    files.push(ManifestEntry {
+        path: name.to_owned(),
+        size: metadata.len(),
+        sha256: reader.sum(),
+    });
+
+    Ok(())
+}
+
+/// Path of a file in bundle, relative to the registry root and separated by `/`
+///
+pub fn bundle_path(root: &Path, path: &Path) -> SkrdResult<String> {
+    let relative = path
+        .strip_prefix(root)
+        .map_err(|_| SkrdError::Custom(format!("{} is not in the registry", path.display())))?;
+
+    let parts: Option<Vec<&str>> = relative.iter().map(|s| s.to_str()).collect();
+    parts
+        .map(|parts| parts.join("/"))
+        .ok_or_else(|| SkrdError::Custom(format!("{} is not valid UTF-8", path.display())))
+}
+
+/// Check if crate `name` is one of `crates`, which is empty for all crates
+///
+fn is_selected(name: &str, crates: &[String]) -> bool {
+    crates.is_empty() || crates.iter().any(|c| c.eq_ignore_ascii_case(name))
+}
+
+/// A `Write` which splits the output into volumes of at most `volume_size` bytes
+///
+/// Volumes are named `{base}.000`, `{base}.001`, ... if `volume_size` is set,
+/// otherwise there is only one volume `{base}`.
+///
+pub struct VolumeWriter {
+    base: PathBuf,
+    volume_size: Option<u64>,
+    current: Option<(File, Sha256, u64)>,
+    volumes: Vec<(PathBuf, [u8; 32])>,
+}
+
+impl VolumeWriter {
+    pub fn new<P: Into<PathBuf>>(base: P, volume_size: Option<u64>) -> Self {
+        VolumeWriter {
+            base: base.into(),
+            volume_size: volume_size.filter(|size| *size > 0),
+            current: None,
+            volumes: Vec::new(),
+        }
+    }
+
+    fn next_volume(&mut self) -> io::Result<()> {
+        self.finish_volume();
+
+        let path = match self.volume_size {
+            Some(_) => volume_path(&self.base, self.volumes.len()),
+            None => self.base.clone(),
+        };
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        info!("Volume {} is created.", path.display());
+        self.volumes.push((path, [0u8; 32]));
+        self.current = Some((file, Sha256::new(), 0));
+        Ok(())
+    }
+
+    fn finish_volume(&mut self) {
+        if let Some((_, sha256, _)) = self.current.take() {
+            if let Some((_, sum)) = self.volumes.last_mut() {
+                sum.copy_from_slice(&sha256.result());
+            }
+        }
+    }
+
+    /// Flush the last volume and write `{base}.sha256`, returns the volume paths
+    ///
+    pub fn finish(mut self) -> SkrdResult<Vec<PathBuf>> {
+        if self.current.is_none() && self.volumes.is_empty() {
+            self.next_volume()?;
+        }
+        self.flush()?;
+        self.finish_volume();
+
+        let mut sums = String::new();
+        for (path, sum) in &self.volumes {
+            let name = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| SkrdError::StaticCustom("invalid volume file name"))?;
+            sums.push_str(&format!("{}  {}\n", hex::encode(sum), name));
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(sums_path(&self.base))?;
+        file.write_all(sums.as_bytes())?;
+
+        Ok(self.volumes.into_iter().map(|(path, _)| path).collect())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let full = match (&self.current, self.volume_size) {
+            (None, _) => true,
+            (Some((_, _, written)), Some(size)) => *written >= size,
+            (Some(_), None) => false,
+        };
+        if full {
+            self.next_volume()?;
+        }
+
+        let volume_size = self.volume_size;
+        let (file, sha256, written) = self
+            .current
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no volume is open"))?;
+
+        let len = match volume_size {
+            Some(size) => buf.len().min((size - *written) as usize),
+            None => buf.len(),
+        };
+
+        let len = file.write(&buf[..len])?;
+        sha256.input(&buf[..len]);
+        *written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((file, _, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A `Read` which computes the SHA-256 sum of what it reads
+///
+pub struct HashReader<R> {
+    inner: R,
+    sha256: Sha256,
+}
+
+impl<R: Read> HashReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashReader {
+            inner,
+            sha256: Sha256::new(),
+        }
+    }
+
+    pub fn sum(self) -> [u8; 32] {
+        let mut sum = [0u8; 32];
+        sum.copy_from_slice(&self.sha256.result());
+        sum
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.sha256.input(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Path of the `index`-th volume of a bundle
+///
+pub fn volume_path(base: &Path, index: usize) -> PathBuf {
+    let mut name = base.as_os_str().to_owned();
+    name.push(format!(".{:03}", index));
+    PathBuf::from(name)
+}
+
+/// Path of the volume sums file of a bundle
+///
+pub fn sums_path(base: &Path) -> PathBuf {
+    let mut name = base.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// Parse a size with an optional suffix: `K`, `M`, `G` or `T` (powers of 1024)
+///
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size '{}': {}", s, e))?;
+    let unit = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" | "KI" => 1 << 10,
+        "M" | "MI" => 1 << 20,
+        "G" | "GI" => 1 << 30,
+        "T" | "TI" => 1 << 40,
+        _ => return Err(format!("invalid size unit '{}'", unit)),
+    };
+
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+/// Find the volumes of a bundle and check them with `{bundle}.sha256`
+///
+pub fn open_volumes(bundle: &Path) -> SkrdResult<Vec<PathBuf>> {
+    let dir = bundle.parent().unwrap_or_else(|| Path::new(""));
+    let content = std::fs::read_to_string(sums_path(bundle)).map_err(|e| {
+        SkrdError::Custom(format!(
+            "{} can not be read: {}",
+            sums_path(bundle).display(),
+            e
+        ))
+    })?;
+
+    let mut volumes = Vec::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let mut parts = line.splitn(2, "  ");
+        let (expected, name) = match (parts.next(), parts.next()) {
+            (Some(sum), Some(name)) => (sum, name),
+            _ => {
+                return Err(SkrdError::Custom(format!(
+                    "invalid line in {}: {}",
+                    sums_path(bundle).display(),
+                    line
+                )))
+            }
+        };
+
+        let path = dir.join(name);
+        let file = File::open(&path).map_err(|e| {
+            SkrdError::Custom(format!(
+                "volume {} can not be opened: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let mut reader = HashReader::new(file);
+        io::copy(&mut reader, &mut io::sink())?;
+        let actual = hex::encode(reader.sum());
+
+        if actual != expected {
+            return Err(SkrdError::Custom(format!(
+                "volume {} is corrupted: expected sha256={}, actual={}",
+                path.display(),
+                expected,
+                actual
+            )));
+        }
+
+        info!("Volume {} is verified.", path.display());
+        volumes.push(path);
+    }
+
+    if volumes.is_empty() {
+        return Err(SkrdError::Custom(format!(
+            "no volume is listed in {}",
+            sums_path(bundle).display()
+        )));
+    }
+
+    Ok(volumes)
+}
+
+/// A `Read` which reads the volumes of a bundle one after another
+///
+pub struct VolumeReader {
+    volumes: std::vec::IntoIter<PathBuf>,
+    current: Option<File>,
+}
+
+impl VolumeReader {
+    pub fn new(volumes: Vec<PathBuf>) -> Self {
+        VolumeReader {
+            volumes: volumes.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(file) = &mut self.current {
+                let len = file.read(buf)?;
+                if len > 0 || buf.is_empty() {
+                    return Ok(len);
+                }
+            }
+
+            match self.volumes.next() {
+                Some(path) => self.current = Some(File::open(path)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Extract a bundle into directory `dest` and check all files with the manifest
+///
+pub fn extract_bundle(bundle: &Path, dest: &Path) -> SkrdResult<Manifest> {
+    let volumes = open_volumes(bundle)?;
+    let mut archive = tar::Archive::new(VolumeReader::new(volumes));
+
+    let mut extracted = BTreeMap::new();
+    let mut manifest = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = {
+            let path = entry.path()?;
+            let parts: Option<Vec<&str>> = path
+                .components()
+                .map(|c| match c {
+                    Component::Normal(part) => part.to_str(),
+                    _ => None,
+                })
+                .collect();
+            parts
+                .filter(|parts| !parts.is_empty())
+                .map(|parts| parts.join("/"))
+                .ok_or_else(|| {
+                    SkrdError::Custom(format!("invalid path in bundle: {}", path.display()))
+                })?
+        };
+
+        if entry.header().entry_type().is_dir() {
+            create_dir_all(dest.join(&name))?;
+            continue;
+        }
+
+        if !entry.header().entry_type().is_file() {
+            return Err(SkrdError::Custom(format!(
+                "{} in bundle is not a regular file",
+                name
+            )));
+        }
+
+        if name == MANIFEST_FILE {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            manifest = Some(serde_json::from_slice::<Manifest>(&content)?);
+            continue;
+        }
+
+        let path = dest.join(&name);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut reader = HashReader::new(&mut entry);
+        let size = io::copy(&mut reader, &mut file)?;
+        extracted.insert(name, (size, reader.sum()));
+    }
+
+    let manifest =
+        manifest.ok_or_else(|| SkrdError::StaticCustom("manifest.json is not found in bundle"))?;
+
+    if manifest.format != BUNDLE_FORMAT {
+        return Err(SkrdError::Custom(format!(
+            "unsupported bundle format: {}",
+            manifest.format
+        )));
+    }
+
+    for file in &manifest.files {
+        match extracted.remove(&file.path) {
+            Some((size, sum)) if size == file.size && sum == file.sha256 => {}
+            Some(_) => {
+                return Err(SkrdError::Custom(format!(
+                    "{} in bundle is corrupted",
+                    file.path
+                )))
+            }
+            None => {
+                return Err(SkrdError::Custom(format!(
+                    "{} is missing in bundle",
+                    file.path
+                )))
+            }
+        }
+    }
+
+    if let Some(name) = extracted.keys().next() {
+        return Err(SkrdError::Custom(format!(
+            "{} in bundle is not listed in manifest",
+            name
+        )));
+    }
+
+    Ok(manifest)
+}
+
+/// Result of `import_bundle`
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// `true` if a new registry is created from the bundle
+    pub created: bool,
+    /// Index `HEAD` before and after the import
+    pub index: Option<(Option<Oid>, Oid)>,
+    /// Paths of imported crate files, relative to the crates directory
+    pub crates: Vec<String>,
+    /// Crate files which are already in the registry
+    pub existing: usize,
+    /// Crate files which are in the registry with different content, they are not imported
+    pub conflicts: Vec<String>,
+}
+
+/// Import a bundle into the registry at `root`
+///
+/// A new registry is created if `root` does not exist, otherwise the index is reset
+/// to the one in the bundle, and only missing crate files are copied.
+///
+pub fn import_bundle(bundle: &Path, root: &Path) -> SkrdResult<ImportReport> {
+    let mut staging = root.as_os_str().to_owned();
+    staging.push(".unpackage");
+    let staging = PathBuf::from(staging);
+
+    if staging.exists() {
+        return Err(SkrdError::Custom(format!(
+            "{} already exists, remove it if no other unpackage is running",
+            staging.display()
+        )));
+    }
+    create_dir_all(&staging)?;
+
+    let result = extract_bundle(bundle, &staging).and_then(|manifest| {
+        info!(
+            "Bundle of registry '{}' ({} files) is verified.",
+            manifest.registry,
+            manifest.files.len()
+        );
+
+        if root.exists() {
+            merge_registry(&staging, root, &manifest)
+        } else if manifest.base.is_some() {
+            Err(SkrdError::Custom(format!(
+                "{} is a delta bundle, it can only be imported into an existing registry",
+                bundle.display()
+            )))
+        } else {
+            create_registry(&staging, root, &manifest)
+        }
+    });
+
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+
+    result
+}
+
+fn create_registry(staging: &Path, root: &Path, manifest: &Manifest) -> SkrdResult<ImportReport> {
+    // http, access and storage settings of the source machine are not carried over
+    let config_path = staging.join(Registry::REGISTRY_TOML_FILE);
+    let config = RegistryConfig::open(&config_path)?.imported();
+    std::fs::write(&config_path, toml::to_string_pretty(&config)?)?;
+
+    std::fs::rename(staging, root)?;
+    let registry = Registry::open(root)?;
+
+    let crates = manifest
+        .files
+        .iter()
+        .filter_map(|file| file.path.strip_prefix("crates/"))
+        .map(str::to_owned)
+        .collect();
+
+    let repo = git2::Repository::open(registry.index_path())?;
+    let head = repo
+        .head()?
+        .target()
+        .ok_or_else(|| SkrdError::StaticCustom("index HEAD of bundle is not found"))?;
+    repo.reference(UNPACKAGED_REF, head, true, "unpackage")?;
+    write_config_json(&registry)?;
+
+    Ok(ImportReport {
+        created: true,
+        index: Some((None, head)),
+        crates,
+        ..ImportReport::default()
+    })
+}
+
+fn merge_registry(staging: &Path, root: &Path, manifest: &Manifest) -> SkrdResult<ImportReport> {
+    let registry = Registry::open(root)?;
+    let _lock = registry.lock("unpackage")?;
+    // crate files are merged by their paths in the bundle
+    let layout = RegistryConfig::open(staging.join(Registry::REGISTRY_TOML_FILE))?
+        .storage()
+        .layout;
+    if layout != registry.layout() {
+        return Err(SkrdError::Custom(format!(
+            "crate storage layout of the bundle ({}) is different from the registry ({})",
+            layout,
+            registry.layout()
+        )));
+    }
+    let index = match &manifest.base {
+        Some(base) => {
+            apply_index_bundle(&registry, &staging.join(INDEX_BUNDLE_FILE), base, manifest)?
+        }
+        None => fast_forward_index(&registry, &staging.join(Registry::INDEX_DIRECTORY))?,
+    };
+    let mut report = ImportReport {
+        index,
+        ..ImportReport::default()
+    };
+
+    let crates_path = staging.join(Registry::CRATES_DIRECTORY);
+    // a bundle without crates has no `crates` directory
+    if crates_path.is_dir() {
+        for entry in WalkDir::new(&crates_path).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let mut relative = bundle_path(&crates_path, entry.path())?;
+            if layout == Layout::Prefix {
+                relative = lowercase_prefix(&relative);
+            }
+            let content = std::fs::read(entry.path())?;
+
+            if let Some(existing) = registry.storage().read(&relative)? {
+                if existing == content {
+                    report.existing += 1;
+                } else {
+                    warn!(
+                        "Crate file {} is different from the bundle, skipped.",
+                        relative
+                    );
+                    report.conflicts.push(relative);
+                }
+                continue;
+            }
+
+            registry.storage().write(&relative, &content)?;
+            info!("Crate file {} is imported.", relative);
+            report.crates.push(relative);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Lowercase the prefix directories of a crate file path in the prefix layout, bundles of
+/// earlier versions have crate files in the ones of the name as given
+///
+fn lowercase_prefix(path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').collect();
+    let prefix_len = parts.len().saturating_sub(2);
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if i < prefix_len {
+                part.to_ascii_lowercase()
+            } else {
+                (*part).to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Update the index of `registry` to the `HEAD` of repository `source`
+///
+/// Returns `HEAD` before and after, or `None` if the index is up to date.
+///
+pub fn fast_forward_index(
+    registry: &Registry,
+    source: &Path,
+) -> SkrdResult<Option<(Option<Oid>, Oid)>> {
+    let source_repo = git2::Repository::open(source)?;
+    let source_head = source_repo.head()?;
+    let branch = source_head
+        .name()
+        .ok_or_else(|| SkrdError::StaticCustom("index HEAD of bundle is invalid"))?
+        .to_owned();
+    let new = source_head
+        .target()
+        .ok_or_else(|| SkrdError::StaticCustom("index HEAD of bundle is not found"))?;
+
+    let repo = git2::Repository::open(registry.index_path())?;
+    let source = source.canonicalize()?;
+    let url = source
+        .to_str()
+        .ok_or_else(|| SkrdError::StaticCustom("invalid bundle index path"))?;
+    repo.remote_anonymous(url)?
+        .fetch(&[&format!("+{}:{}", branch, BUNDLE_REF)], None, None)?;
+
+    let result = reset_index(registry, &repo, new);
+    if let Ok(mut reference) = repo.find_reference(BUNDLE_REF) {
+        reference.delete()?;
+    }
+
+    result
+}
+
+/// Apply the git bundle of a delta bundle to the index of `registry`
+///
+/// The base revision of the delta bundle must exist in the index.
+///
+fn apply_index_bundle(
+    registry: &Registry,
+    index_bundle: &Path,
+    base: &str,
+    manifest: &Manifest,
+) -> SkrdResult<Option<(Option<Oid>, Oid)>> {
+    let repo = git2::Repository::open(registry.index_path())?;
+
+    let base_id = Oid::from_str(base)?;
+    if repo.find_commit(base_id).is_err() {
+        return Err(SkrdError::Custom(format!(
+            "base revision {} of the delta bundle is not found in the index, import the bundles before it first",
+            base
+        )));
+    }
+
+    let new = manifest
+        .index_head
+        .as_ref()
+        .ok_or_else(|| SkrdError::StaticCustom("index HEAD of bundle is not found"))
+        .and_then(|head| Oid::from_str(head).map_err(SkrdError::from))?;
+
+    // TODO: no dependency on `git`
+    let output = PsCommand::new("git")
+        .arg("fetch")
+        .arg("--no-tags")
+        .arg(index_bundle.canonicalize()?)
+        .arg(format!("+HEAD:{}", BUNDLE_REF))
+        .current_dir(registry.index_path())
+        .output()?;
+    if !output.status.success() {
+        return Err(SkrdError::Custom(format!(
+            "git fetch error: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let result = reset_index(registry, &repo, new);
+    if let Ok(mut reference) = repo.find_reference(BUNDLE_REF) {
+        reference.delete()?;
+    }
+
+    result
+}
+
+/// Reset the index of `registry` to the bundle commit `new`, and commit `config.json` of
+/// the registry on top of it
+///
+/// The index must not have commits other than the ones of `config.json` after the last
+/// imported bundle, and `new` must be a descendant of it.
+///
+fn reset_index(
+    registry: &Registry,
+    repo: &git2::Repository,
+    new: Oid,
+) -> SkrdResult<Option<(Option<Oid>, Oid)>> {
+    let old = repo.head().ok().and_then(|head| head.target());
+
+    if let Some(old) = old {
+        // indexes unpackaged before `UNPACKAGED_REF` is kept have no such reference
+        let previous = repo.refname_to_id(UNPACKAGED_REF).ok();
+        let mut base = old;
+        while Some(base) != previous && is_config_commit(repo, base)? {
+            base = repo.find_commit(base)?.parent_id(0)?;
+        }
+        match previous {
+            Some(previous) if previous != base => {
+                return Err(SkrdError::Custom(format!(
+                    "index has commits after the last imported bundle ({}), it can not be updated from bundles",
+                    previous
+                )));
+            }
+            None => {
+                repo.reference(UNPACKAGED_REF, base, true, "unpackage")?;
+            }
+            _ => {}
+        }
+
+        if base == new || repo.graph_descendant_of(base, new)? {
+            return Ok(None);
+        }
+        if !repo.graph_descendant_of(new, base)? {
+            return Err(SkrdError::Custom(format!(
+                "index of the bundle ({}) can not be fast-forwarded from {}",
+                new, base
+            )));
+        }
+    }
+
+    repo.reset(
+        repo.find_commit(new)?.as_object(),
+        git2::ResetType::Hard,
+        None,
+    )?;
+    repo.reference(UNPACKAGED_REF, new, true, "unpackage")?;
+    write_config_json(registry)?;
+
+    Ok(Some((old, new)))
+}
+
+/// Check if `id` is a commit which only changes `config.json`
+///
+fn is_config_commit(repo: &git2::Repository, id: Oid) -> SkrdResult<bool> {
+    let commit = repo.find_commit(id)?;
+    if commit.parent_count() != 1 {
+        return Ok(false);
+    }
+    let diff = repo.diff_tree_to_tree(
+        Some(&commit.parent(0)?.tree()?),
+        Some(&commit.tree()?),
+        None,
+    )?;
+
+    Ok(diff.deltas().all(|delta| {
+        delta.new_file().path() == Some(Path::new("config.json"))
+            && delta.old_file().path() == Some(Path::new("config.json"))
+    }))
+}
+
+/// Get the SHA-256 sum of a file
+///
+pub fn file_sum(path: &Path) -> SkrdResult<[u8; 32]> {
+    let mut reader = HashReader::new(File::open(path)?);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.sum())
+}
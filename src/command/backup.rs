@@ -0,0 +1,68 @@
+use crate::backup::backup;
+use crate::error::SkrdResult;
+use crate::registry::Registry;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Backup {
+    #[structopt(
+        help = "Set the backup directory, it is created if it does not exist",
+        value_name = "BACKUP PATH"
+    )]
+    pub dest: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl Backup {
+    pub fn backup(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+        // the lock keeps writers away, so the index and crates of the snapshot are consistent
+        let _lock = registry.lock("backup")?;
+
+        info!(
+            "Start to back up registry '{}' into {} ...",
+            registry.config().name(),
+            self.dest.display()
+        );
+
+        let (snapshot, report) = backup(&registry, &self.dest)?;
+
+        info!(
+            "Snapshot {} is created at index commit {}.",
+            snapshot.id,
+            snapshot.index_head.as_deref().unwrap_or("-")
+        );
+        info!(
+            "Total: {} files, {} crates ({} bytes), {} stored ({} bytes), {} already exist, {} missing, {} corrupted.",
+            snapshot.files.len(),
+            snapshot.crates.len(),
+            snapshot.size(),
+            report.stored,
+            report.bytes,
+            report.existing,
+            report.missing.len(),
+            report.corrupted.len()
+        );
+        for missing in &report.missing {
+            warn!("Missing: {}", missing);
+        }
+        for corrupted in &report.corrupted {
+            warn!("Corrupted: {}", corrupted);
+        }
+
+        Ok(())
+    }
+}
@@ -15,10 +15,10 @@ pub struct Create {
         help = "Set the registry name",
         value_name = "REGISTRY NAME"
     )]
-    name: Option<String>,
+    pub name: Option<String>,
 
     #[structopt(help = "Set the registry path", value_name = "path")]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
 impl Create {
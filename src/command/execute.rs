@@ -1,4 +1,14 @@
-use std::path::PathBuf;
+use crate::bundle::parse_size;
+use crate::command::{create, mirror, package, update};
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::{check_index, collect_garbage, verify_crates};
+use crate::registry::{Registry, CRATES_IO_INDEX};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -13,3 +23,342 @@ pub struct Execute {
     )]
     toml: PathBuf,
 }
+
+/// A batch job
+///
+/// Example:
+///
+/// ```toml
+/// registry = "mymirror"
+/// report = "report.json"
+///
+/// [[steps]]
+/// action = "update"
+///
+/// [[steps]]
+/// action = "verify"
+/// continue-on-error = true
+///
+/// [[steps]]
+/// action = "package"
+/// output = "mymirror.tar"
+/// volume-size = "4G"
+/// ```
+///
+/// Relative paths are relative to the directory of the job file.
+///
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Job {
+    /// Registry path of the steps without one
+    registry: Option<PathBuf>,
+    /// Path of the JSON report written after all steps
+    report: Option<PathBuf>,
+    /// Default of the steps without `continue-on-error`
+    #[serde(default)]
+    continue_on_error: bool,
+    steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Step {
+    #[serde(flatten)]
+    action: Action,
+    registry: Option<PathBuf>,
+    continue_on_error: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    Create {
+        name: Option<String>,
+    },
+    Mirror {
+        name: Option<String>,
+        source: Option<String>,
+    },
+    Update,
+    Verify,
+    Package {
+        output: PathBuf,
+        #[serde(rename = "volume-size")]
+        volume_size: Option<String>,
+        #[serde(default)]
+        crates: Vec<String>,
+        since: Option<String>,
+    },
+    Gc {
+        /// Only crate files to be removed are listed unless it is set
+        #[serde(default)]
+        force: bool,
+    },
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let name = match self {
+            Action::Create { .. } => "create",
+            Action::Mirror { .. } => "mirror",
+            Action::Update => "update",
+            Action::Verify => "verify",
+            Action::Package { .. } => "package",
+            Action::Gc { .. } => "gc",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Report of a batch job
+#[derive(Serialize, Debug)]
+pub struct JobReport {
+    job: PathBuf,
+    started: String,
+    finished: String,
+    steps: Vec<StepReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StepReport {
+    action: String,
+    registry: PathBuf,
+    status: StepStatus,
+    seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+impl Execute {
+    pub fn execute(self) -> SkrdResult<()> {
+        let mut content = String::new();
+        File::open(&self.toml)?.read_to_string(&mut content)?;
+        let Job {
+            registry,
+            report: report_path,
+            continue_on_error,
+            steps,
+        } = toml::from_str(&content)?;
+
+        let base = self.toml.parent().map(Path::to_owned).unwrap_or_default();
+
+        info!(
+            "Start to execute {} ({} steps) ...",
+            self.toml.display(),
+            steps.len()
+        );
+
+        let mut report = JobReport {
+            job: self.toml.clone(),
+            started: chrono::Local::now().to_rfc3339(),
+            finished: String::new(),
+            steps: Vec::with_capacity(steps.len()),
+        };
+
+        let mut aborted = false;
+        for (i, step) in steps.into_iter().enumerate() {
+            let registry = step
+                .registry
+                .as_ref()
+                .or(registry.as_ref())
+                .map(|path| base.join(path))
+                .unwrap_or_else(|| base.clone());
+            let action = step.action.to_string();
+
+            if aborted {
+                info!("Step {} ({}) is skipped.", i + 1, action);
+                report.steps.push(StepReport {
+                    action,
+                    registry,
+                    status: StepStatus::Skipped,
+                    seconds: 0.0,
+                    error: None,
+                });
+                continue;
+            }
+
+            info!("Step {} ({}) on {} ...", i + 1, action, registry.display());
+            let start = Instant::now();
+            let result = step.action.run(&registry, &base);
+            let elapsed = start.elapsed();
+            let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+
+            let (status, error) = match result {
+                Ok(()) => (StepStatus::Succeeded, None),
+                Err(e) => {
+                    error!("Step {} ({}) failed: {}", i + 1, action, e);
+                    if !step.continue_on_error.unwrap_or(continue_on_error) {
+                        aborted = true;
+                    }
+                    (StepStatus::Failed, Some(e.to_string()))
+                }
+            };
+
+            report.steps.push(StepReport {
+                action,
+                registry,
+                status,
+                seconds,
+                error,
+            });
+        }
+
+        report.finished = chrono::Local::now().to_rfc3339();
+
+        let count = |status| report.steps.iter().filter(|s| s.status == status).count();
+        let (succeeded, failed, skipped) = (
+            count(StepStatus::Succeeded),
+            count(StepStatus::Failed),
+            count(StepStatus::Skipped),
+        );
+        info!(
+            "Total: {} steps succeeded, {} failed, {} skipped.",
+            succeeded, failed, skipped
+        );
+
+        if let Some(path) = &report_path {
+            let path = base.join(path);
+            serde_json::to_writer_pretty(File::create(&path)?, &report)?;
+            info!("Report is written to {}.", path.display());
+        }
+
+        if failed > 0 {
+            return Err(SkrdError::Custom(format!(
+                "{} of {} steps failed",
+                failed,
+                report.steps.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Action {
+    /// Run the command of this action on the registry at `path`
+    ///
+    fn run(self, path: &Path, base: &Path) -> SkrdResult<()> {
+        match self {
+            Action::Create { name } => create::Create {
+                name,
+                path: path.to_owned(),
+            }
+            .create(),
+            Action::Mirror { name, source } => mirror::Mirror {
+                name,
+                path: path.to_owned(),
+                source: source.unwrap_or_else(|| CRATES_IO_INDEX.to_owned()),
+            }
+            .mirror(),
+            Action::Update => update::Update {
+                follow: false,
+                registry: Some(Registry::open(path)?),
+            }
+            .update(),
+            Action::Verify => verify(&Registry::open(path)?),
+            Action::Package {
+                output,
+                volume_size,
+                crates,
+                since,
+            } => package::Package {
+                volume_size: volume_size
+                    .map(|size| parse_size(&size).map_err(SkrdError::Custom))
+                    .transpose()?,
+                crates,
+                since,
+                output: base.join(output),
+                registry: Some(Registry::open(path)?),
+            }
+            .package(),
+            Action::Gc { force } => gc(&Registry::open(path)?, force),
+        }
+    }
+}
+
+/// Check the index and verify crate files against their checksums
+///
+fn verify(registry: &Registry) -> SkrdResult<()> {
+    info!("Verifying '{}' ...", registry.config().name());
+
+    let index = check_index(registry)?;
+    for issue in &index.issues {
+        warn!("{}", issue);
+    }
+
+    let crates = verify_crates(registry);
+    for version in &crates.missing {
+        warn!("Crate file of {} is missing.", version);
+    }
+    for path in &crates.corrupted {
+        warn!("Crate file {} is corrupted.", path.display());
+    }
+
+    info!(
+        "Total: {} index problems, {} crate files checked, {} missing, {} corrupted.",
+        index.issues.len(),
+        crates.checked,
+        crates.missing.len(),
+        crates.corrupted.len()
+    );
+
+    let problems = index.issues.len() + crates.missing.len() + crates.corrupted.len();
+    if problems > 0 {
+        return Err(SkrdError::Custom(format!(
+            "{} problems found in registry '{}'",
+            problems,
+            registry.config().name()
+        )));
+    }
+
+    Ok(())
+}
+
+/// List crate files which belong to no version in the index, and remove them if `force` is set
+///
+fn gc(registry: &Registry, force: bool) -> SkrdResult<()> {
+    let _lock = if force {
+        Some(registry.lock("gc")?)
+    } else {
+        None
+    };
+
+    info!("Collecting garbage of '{}' ...", registry.config().name());
+
+    let report = collect_garbage(registry, force)?;
+    for path in &report.files {
+        if force {
+            info!("{} is removed.", path.display());
+        } else {
+            info!("{} would be removed.", path.display());
+        }
+    }
+    for path in &report.kept {
+        warn!(
+            "{} is kept: not a crate file, or a crate file of a version in the index.",
+            path.display()
+        );
+    }
+
+    info!(
+        "Total: {} files ({} bytes) {}, {} other files kept.",
+        report.files.len(),
+        report.bytes,
+        if force {
+            "removed"
+        } else {
+            "to be removed, set `force = true` to remove them"
+        },
+        report.kept.len()
+    );
+
+    Ok(())
+}
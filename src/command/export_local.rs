@@ -0,0 +1,74 @@
+use crate::error::SkrdResult;
+use crate::export::{export_local, CrateSelection};
+use crate::registry::Registry;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct ExportLocal {
+    #[structopt(
+        long = "lockfile",
+        number_of_values = 1,
+        help = "Only export the packages locked in this Cargo.lock, can be used multiple times",
+        value_name = "PATH"
+    )]
+    pub lockfiles: Vec<PathBuf>,
+
+    #[structopt(
+        long = "crate",
+        number_of_values = 1,
+        help = "Only export all versions of this crate, can be used multiple times",
+        value_name = "CRATE NAME"
+    )]
+    pub crates: Vec<String>,
+
+    #[structopt(help = "Set the local registry path", value_name = "OUTPUT PATH")]
+    pub output: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl ExportLocal {
+    pub fn export_local(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+
+        let mut selection = CrateSelection {
+            names: self.crates,
+            ..CrateSelection::default()
+        };
+        for lockfile in &self.lockfiles {
+            selection.add_lockfile(lockfile)?;
+        }
+
+        info!(
+            "Start to export registry '{}' into local registry {} ...",
+            registry.config().name(),
+            self.output.display()
+        );
+
+        let report = export_local(&registry, &self.output, &selection)?;
+
+        for version in &report.missing {
+            warn!("{} is not found in the registry.", version);
+        }
+        info!(
+            "Total: {} versions exported, {} crates copied, {} missing.",
+            report.versions,
+            report.crates,
+            report.missing.len()
+        );
+
+        Ok(())
+    }
+}
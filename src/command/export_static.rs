@@ -0,0 +1,64 @@
+use crate::error::SkrdResult;
+use crate::export::export_static;
+use crate::registry::Registry;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct ExportStatic {
+    #[structopt(
+        long = "url",
+        help = "Set the url the site will be served at, default to the url of the registry",
+        value_name = "URL"
+    )]
+    pub url: Option<String>,
+
+    #[structopt(help = "Set the output directory", value_name = "OUTPUT PATH")]
+    pub output: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl ExportStatic {
+    pub fn export_static(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+
+        let url = self.url.unwrap_or_else(|| registry.base_url());
+
+        info!(
+            "Start to export registry '{}' into {} ...",
+            registry.config().name(),
+            self.output.display()
+        );
+
+        let report = export_static(&registry, &self.output, &url)?;
+
+        info!(
+            "Total: {} index files written, {} crates copied, {} files unchanged, {} files removed.",
+            report.index_files, report.crates, report.unchanged, report.removed
+        );
+        if report.missing > 0 {
+            warn!(
+                "{} versions in the index have no crate files.",
+                report.missing
+            );
+        }
+        info!(
+            "The registry can be used as sparse+{}/index/",
+            url.trim_end_matches('/')
+        );
+
+        Ok(())
+    }
+}
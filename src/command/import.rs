@@ -0,0 +1,99 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::import::{import_crates, import_local_registry, import_vendor, ImportFormat};
+use crate::registry::Registry;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Import {
+    #[structopt(
+        long = "format",
+        help = "Set the layout of the directory: crates, vendor or local-registry, detected if not set",
+        value_name = "FORMAT",
+        parse(try_from_str)
+    )]
+    pub format: Option<ImportFormat>,
+
+    #[structopt(
+        help = "Set the directory of .crate files, `cargo vendor` or `cargo local-registry`",
+        value_name = "DIRECTORY"
+    )]
+    pub dir: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl Import {
+    pub fn import(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+
+        // the index of a replica is reset to the one of its primary
+        if let Some(replica) = registry.replica_config() {
+            return Err(SkrdError::Custom(format!(
+                "registry '{}' is a replica, import crates into its primary {} instead",
+                registry.config().name(),
+                replica.primary
+            )));
+        }
+        let _lock = registry.lock("import")?;
+
+        info!(
+            "Start to import {} into '{}' ...",
+            self.dir.display(),
+            registry.config().name()
+        );
+
+        if registry.mirror_config().is_some() {
+            warn!("This registry is a mirror, imported crates will diverge from its source.");
+        }
+
+        let format = match self.format {
+            Some(format) => format,
+            None => {
+                let format = ImportFormat::detect(&self.dir)?;
+                info!("{} is imported as {}.", self.dir.display(), format);
+                format
+            }
+        };
+
+        let report = match format {
+            ImportFormat::Crates => import_crates(&registry, &self.dir)?,
+            ImportFormat::Vendor => import_vendor(&registry, &self.dir)?,
+            ImportFormat::LocalRegistry => import_local_registry(&registry, &self.dir)?,
+        };
+
+        for (path, error) in &report.failed {
+            warn!("{} can not be imported: {}", path.display(), error);
+        }
+        for version in &report.duplicates {
+            info!("Duplicate: {}", version);
+        }
+        for version in &report.conflicts {
+            warn!("Conflict: {}", version);
+        }
+        if let Some(oid) = report.commit {
+            info!("Index is committed. (commit id: {})", oid);
+        }
+
+        info!(
+            "Total: {} crates imported, {} duplicates, {} conflicts, {} failed.",
+            report.imported.len(),
+            report.duplicates.len(),
+            report.conflicts.len(),
+            report.failed.len()
+        );
+
+        Ok(())
+    }
+}
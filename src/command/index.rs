@@ -0,0 +1,164 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::{check_index, fix_index, read_audit_log, rebuild_index};
+use crate::registry::Registry;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub enum Index {
+    /// Check the consistency of the index
+    #[structopt(name = "check")]
+    Check(Check),
+
+    /// Rebuild the index from the crate files
+    #[structopt(name = "rebuild")]
+    Rebuild(Rebuild),
+}
+
+impl Index {
+    pub fn index(self) -> SkrdResult<()> {
+        match self {
+            Index::Check(check) => check.check(),
+            Index::Rebuild(rebuild) => rebuild.rebuild(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Check {
+    #[structopt(long = "fix", help = "Rewrite and commit the corrected index files")]
+    pub fix: bool,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl Check {
+    pub fn check(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+        let _lock = if self.fix {
+            Some(registry.lock("index fix")?)
+        } else {
+            None
+        };
+
+        info!("Checking index of '{}' ...", registry.config().name());
+
+        let report = check_index(&registry)?;
+        for issue in &report.issues {
+            warn!("{}", issue);
+        }
+        info!(
+            "Total: {} index files and {} versions are checked, {} problems found.",
+            report.files,
+            report.versions,
+            report.issues.len()
+        );
+
+        if report.issues.is_empty() {
+            return Ok(());
+        }
+
+        if !self.fix {
+            return Err(SkrdError::Custom(format!(
+                "{} problems found in index, run with `--fix` to repair them",
+                report.issues.len()
+            )));
+        }
+
+        if registry.mirror_config().is_some() {
+            warn!("This registry is a mirror, fixes will diverge from its source.");
+        }
+
+        match fix_index(&registry, &report)? {
+            Some(oid) => info!("Index is fixed. (commit id: {})", oid),
+            None => info!("Index is fixed."),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Rebuild {
+    #[structopt(
+        long = "audit-log",
+        help = "Restore yanked versions from an audit log",
+        value_name = "AUDIT LOG"
+    )]
+    pub audit_log: Option<PathBuf>,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl Rebuild {
+    pub fn rebuild(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+
+        if registry.virtual_config().is_some() {
+            return Err(SkrdError::StaticCustom(
+                "the index of a virtual registry is merged, run `skrd update` instead",
+            ));
+        }
+        let changes = match &self.audit_log {
+            Some(path) => read_audit_log(path)?,
+            None => Vec::new(),
+        };
+        let _lock = registry.lock("index rebuild")?;
+
+        info!(
+            "Rebuilding index of '{}' from crate files ...",
+            registry.config().name()
+        );
+        if registry.mirror_config().is_some() {
+            warn!("This registry is a mirror, the rebuilt index will diverge from its source.");
+        }
+
+        let report = rebuild_index(&registry, &changes)?;
+
+        if let Some(commit) = report.commit {
+            info!("Index is rebuilt. (commit id: {})", commit);
+        }
+        if let Some(old_index) = &report.old_index {
+            info!(
+                "The old index is kept in {}, remove it when it is not needed.",
+                old_index.display()
+            );
+        }
+        for version in &report.unknown {
+            warn!("{} in the audit log has no crate file.", version);
+        }
+        info!(
+            "Total: {} versions ({} yanked), {} failed, {} misplaced, {} conflicts, {} unknown in the audit log.",
+            report.versions,
+            report.yanked,
+            report.failed.len(),
+            report.misplaced.len(),
+            report.conflicts.len(),
+            report.unknown.len()
+        );
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::{SkrdError, SkrdResult};
-use crate::registry::Registry;
+use crate::registry::{Registry, CRATES_IO_INDEX};
 use crate::util::download_crates;
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -11,17 +11,17 @@ pub struct Mirror {
         help = "Set the registry name",
         value_name = "REGISTRY NAME"
     )]
-    name: Option<String>,
+    pub name: Option<String>,
 
     #[structopt(help = "Set the registry path", value_name = "path")]
-    path: PathBuf,
+    pub path: PathBuf,
 
     #[structopt(
         help = "Set the url of the server to be mirrored",
         value_name = "source",
-        default_value = "https://github.com/rust-lang/crates.io-index"
+        raw(default_value = "CRATES_IO_INDEX")
     )]
-    source: String,
+    pub source: String,
 }
 
 impl Mirror {
@@ -38,6 +38,7 @@ impl Mirror {
         };
 
         let registry = Registry::mirror(&self.path, &name, &self.source)?;
+        let _lock = registry.lock("mirror")?;
 
         info!("{} cloned.", self.source);
 
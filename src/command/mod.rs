@@ -1,11 +1,21 @@
 use structopt::StructOpt;
 
+pub mod backup;
 pub mod create;
 pub mod execute;
+pub mod export_local;
+pub mod export_static;
+pub mod import;
+pub mod index;
 pub mod mirror;
 pub mod package;
+pub mod replica;
+pub mod restore;
 pub mod serve;
+pub mod storage;
+pub mod unpackage;
 pub mod update;
+pub mod virtual_registry;
 
 /// SilkRoad Command
 #[derive(Debug, StructOpt)]
@@ -23,6 +33,34 @@ pub enum Command {
     #[structopt(name = "update")]
     Update(update::Update),
 
+    /// Create a replica which follows another silkroad registry
+    #[structopt(name = "replica")]
+    Replica(replica::Replica),
+
+    /// Create a virtual registry merged from other registries
+    #[structopt(name = "virtual")]
+    Virtual(virtual_registry::VirtualRegistry),
+
+    /// Import .crate files into a registry
+    #[structopt(name = "import")]
+    Import(import::Import),
+
+    /// Check or repair the index of a registry
+    #[structopt(name = "index")]
+    Index(index::Index),
+
+    /// Manage the storage of crate files
+    #[structopt(name = "storage")]
+    Storage(storage::Storage),
+
+    /// Take an incremental snapshot of a registry
+    #[structopt(name = "backup")]
+    Backup(backup::Backup),
+
+    /// Restore a snapshot as a new registry
+    #[structopt(name = "restore")]
+    Restore(restore::Restore),
+
     /// Start a full featured registry
     #[structopt(name = "serve")]
     Serve(serve::Serve),
@@ -31,6 +69,18 @@ pub enum Command {
     #[structopt(name = "package")]
     Package(package::Package),
 
+    /// Unpack a bundle into a new or existing registry
+    #[structopt(name = "unpackage")]
+    Unpackage(unpackage::Unpackage),
+
+    /// Export a registry as a static site
+    #[structopt(name = "export-static")]
+    ExportStatic(export_static::ExportStatic),
+
+    /// Export crates to a cargo local registry
+    #[structopt(name = "export-local")]
+    ExportLocal(export_local::ExportLocal),
+
     /// Execute a command in a TOML file
     #[structopt(name = "exec")]
     Execute(execute::Execute),
@@ -1,4 +1,79 @@
+use crate::bundle::{create_bundle, parse_size};
+use crate::error::SkrdResult;
+use crate::registry::Registry;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
-pub struct Package {}
+pub struct Package {
+    #[structopt(
+        long = "volume-size",
+        help = "Split the bundle into volumes of this size, e.g. 4G or 700M",
+        value_name = "SIZE",
+        parse(try_from_str = "parse_size")
+    )]
+    pub volume_size: Option<u64>,
+
+    #[structopt(
+        long = "crate",
+        number_of_values = 1,
+        help = "Only pack the crate files of this crate, can be used multiple times",
+        value_name = "CRATE NAME"
+    )]
+    pub crates: Vec<String>,
+
+    #[structopt(
+        long = "since",
+        help = "Create a delta bundle of the changes after an index commit or bundle id",
+        value_name = "COMMIT"
+    )]
+    pub since: Option<String>,
+
+    #[structopt(help = "Set the bundle path", value_name = "BUNDLE PATH")]
+    pub output: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl Package {
+    pub fn package(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+        let _lock = registry.lock("package")?;
+
+        info!(
+            "Start to pack registry '{}' into {} ...",
+            registry.config().name(),
+            self.output.display()
+        );
+
+        let (manifest, volumes) = create_bundle(
+            &registry,
+            &self.output,
+            self.volume_size,
+            &self.crates,
+            self.since.as_deref(),
+        )?;
+
+        info!(
+            "Total: {} files ({} bytes) are packed into {} volumes.",
+            manifest.files.len(),
+            manifest.files.iter().map(|f| f.size).sum::<u64>(),
+            volumes.len()
+        );
+        if let Some(id) = &manifest.index_head {
+            info!("Bundle id: {}", id);
+        }
+        Ok(())
+    }
+}
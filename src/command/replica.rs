@@ -0,0 +1,84 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::registry::Registry;
+use crate::replica::{sync, ReplicaConfig, SyncReport};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Replica {
+    #[structopt(
+        long = "name",
+        help = "Set the registry name",
+        value_name = "REGISTRY NAME"
+    )]
+    pub name: Option<String>,
+
+    #[structopt(
+        long = "forward-writes",
+        help = "Forward publish, yank and owner changes to the primary instead of refusing them"
+    )]
+    pub forward_writes: bool,
+
+    #[structopt(
+        long = "token",
+        help = "Set the token of the change feed of the primary",
+        value_name = "TOKEN"
+    )]
+    pub token: Option<String>,
+
+    #[structopt(help = "Set the registry path", value_name = "path")]
+    pub path: PathBuf,
+
+    #[structopt(
+        help = "Set the url of the primary registry, e.g. http://crates.example.com/mymirror",
+        value_name = "primary"
+    )]
+    pub primary: String,
+}
+
+impl Replica {
+    pub fn create(self) -> SkrdResult<()> {
+        let name = if let Some(name) = &self.name {
+            name.clone()
+        } else {
+            self.path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| SkrdError::StaticCustom("the registry path provided is invalid"))?
+                .to_owned()
+        };
+
+        let mut replica = ReplicaConfig::new(&self.primary);
+        replica.forward_writes = self.forward_writes;
+        replica.token = self.token;
+
+        let mut registry = Registry::create(&self.path, &name)?;
+        registry.config_mut().set_replica(replica);
+        registry.save_config()?;
+        git2::Repository::init(registry.index_path())?;
+        let _lock = registry.lock("replica")?;
+
+        info!("Start to synchronize with {} ...", self.primary);
+
+        let client = reqwest::ClientBuilder::new().gzip(false).build()?;
+        log_sync(&sync(&registry, &client)?);
+
+        info!("Replica is created.");
+        Ok(())
+    }
+}
+
+pub fn log_sync(report: &SyncReport) {
+    match report.index {
+        Some((Some(old), new)) => info!("Index is synchronized: {}..{}", old, new),
+        Some((None, new)) => info!("Index is synchronized at {}.", new),
+        None => info!("Index is up to date."),
+    }
+    info!(
+        "Total: {} crates checked, {} downloaded ({} bytes), {} failed.",
+        report.checked,
+        report.downloaded,
+        report.bytes,
+        report.failed.len()
+    );
+}
@@ -0,0 +1,69 @@
+use crate::backup::{restore, Snapshot};
+use crate::error::{SkrdError, SkrdResult};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Restore {
+    #[structopt(
+        long = "snapshot",
+        help = "Restore this snapshot instead of the latest one",
+        value_name = "SNAPSHOT ID"
+    )]
+    pub snapshot: Option<String>,
+
+    #[structopt(help = "Set the backup directory", value_name = "BACKUP PATH")]
+    pub backup: PathBuf,
+
+    #[structopt(
+        help = "Set the path of the new registry",
+        value_name = "REGISTRY PATH"
+    )]
+    pub path: PathBuf,
+}
+
+impl Restore {
+    pub fn restore(self) -> SkrdResult<()> {
+        let snapshot = Snapshot::open(&self.backup, self.snapshot.as_deref())?;
+
+        info!(
+            "Start to restore snapshot {} of registry '{}' into {} ...",
+            snapshot.id,
+            snapshot.registry,
+            self.path.display()
+        );
+
+        let report = restore(&self.backup, &snapshot, &self.path)?;
+
+        info!(
+            "Total: {} files, {} crates restored, {} index issues, {} crates checked, {} missing, {} corrupted.",
+            report.files,
+            report.crates,
+            report.index_issues,
+            report.verified.checked,
+            report.verified.missing.len(),
+            report.verified.corrupted.len()
+        );
+        for missing in &report.verified.missing {
+            warn!("Missing: {}", missing);
+        }
+        for corrupted in &report.verified.corrupted {
+            warn!("Corrupted: {}", corrupted.display());
+        }
+
+        // index issues are restored as they were in the registry
+        if report.index_issues > 0 {
+            warn!(
+                "Index of the restored registry has problems, run `skrd index check` for details."
+            );
+        }
+        if !report.verified.missing.is_empty() || !report.verified.corrupted.is_empty() {
+            return Err(SkrdError::Custom(format!(
+                "crate files of the registry restored at {} fail verification",
+                self.path.display()
+            )));
+        }
+
+        Ok(())
+    }
+}
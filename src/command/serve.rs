@@ -1,8 +1,9 @@
 use actix_web::{
+    error::BlockingError,
     guard,
-    http::header,
+    http::{header, StatusCode},
     middleware::{DefaultHeaders, Logger},
-    web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+    web, App, Either, HttpRequest, HttpResponse, HttpServer, Responder,
 };
 use std::{
     fs::File,
@@ -10,21 +11,45 @@ use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
     process::Command as PsCommand,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 
+use crate::archive::{normalize_source_path, CrateArchive, SourceEntry, SourceFile, SourceNode};
+use crate::diff::CrateDiff;
 use crate::error::SkrdError;
+use crate::feed::{read_changes, ChangeNotifier, ChangePage, MAX_STREAMS};
+use crate::index::{ReverseDependency, ReverseIndexCache};
+use crate::overlay::Overlay;
+use crate::replica::{ReplicaConfig, FEED_WAIT};
 use crate::util::*;
-use crate::{error::SkrdResult, registry::Registry};
+use crate::{
+    error::SkrdResult,
+    registry::{AccessControl, Registry, RegistryConfig, ServeConfig},
+};
 use actix_http::httpmessage::HttpMessage;
+use futures::{future, Future, Stream};
 use mime::Mime;
+use serde_derive::{Deserialize, Serialize};
 use std::io::Write;
 use std::process::Stdio;
 use std::str::FromStr;
 
+/// Largest body of a request, e.g. a published crate
+const PAYLOAD_LIMIT: usize = 10 * 1024 * 1024;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "serve")]
 pub struct Serve {
+    #[structopt(
+        long = "config",
+        help = "Serve all registries listed in a server config file",
+        value_name = "CONFIG PATH",
+        conflicts_with = "registry"
+    )]
+    config: Option<PathBuf>,
+
     #[structopt(
         help = "Set the registry path",
         value_name = "REGISTRY PATH",
@@ -35,101 +60,410 @@ pub struct Serve {
 
 impl Serve {
     pub fn serve(self) -> SkrdResult<()> {
-        // if registry is not specified, try current directory
-        let registry = if let Some(registry) = self.registry {
-            registry
+        let (http, registries) = if let Some(path) = &self.config {
+            let (config, registries) = ServeConfig::open(path)?;
+            (config.http, registries)
         } else {
-            let current_dir = std::env::current_dir()?;
-            Registry::open(current_dir)?
+            // if registry is not specified, try current directory
+            let registry = if let Some(registry) = self.registry {
+                registry
+            } else {
+                let current_dir = std::env::current_dir()?;
+                Registry::open(current_dir)?
+            };
+            (registry.config().http().clone(), vec![registry])
         };
 
-        let config = registry.config();
-        info!("Registry '{}' loaded.", config.name());
-        info!(
-            "Access Control => git-receive-pack: {}, git-upload-pack: {}",
-            config.receive_on(),
-            config.upload_on()
-        );
+        if registries.is_empty() {
+            return Err(SkrdError::StaticCustom("no registry to serve"));
+        }
+
+        let mut overlays = Vec::with_capacity(registries.len());
+        for registry in &registries {
+            let config = registry.config();
+            info!("Registry '{}' loaded.", config.name());
+            let overlay = Overlay::open(registry)?;
+            for backing in overlay.registries() {
+                info!(
+                    "Virtual registry '{}' is backed by '{}'.",
+                    config.name(),
+                    backing.config().name()
+                );
+            }
+            overlays.push(overlay);
+            log_access(config.name(), config.access());
+        }
+        let accesses: Vec<_> = registries.iter().map(Access::new).collect();
+        let reverse_indexes: Vec<_> = registries
+            .iter()
+            .map(|_| ReverseIndexCache::default())
+            .collect();
+        let notifiers: Vec<_> = registries
+            .iter()
+            .map(|registry| ChangeNotifier::new(registry.index_path()))
+            .collect();
 
         let sys = actix_rt::System::new("silk_road");
 
-        // HttpServer shared data
-        let reg = registry.clone();
+        // every registry has its own scope and shared data
+        let regs: Vec<_> = registries
+            .iter()
+            .cloned()
+            .zip(overlays)
+            .zip(accesses.iter().cloned())
+            .zip(reverse_indexes)
+            .zip(notifiers)
+            .collect();
         let server = HttpServer::new(move || {
-            App::new()
-                .data(reg.clone())
+            let mut app = App::new()
                 .wrap(Logger::default())
                 .wrap(DefaultHeaders::new().header(
                     "server",
                     format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-                ))
-                .service(
+                ));
+
+            for ((((reg, overlay), access), reverse_index), notifier) in &regs {
+                app = app.service(
                     web::scope(&("/".to_owned() + reg.config().name()))
+                        .data(reg.clone())
+                        .data(overlay.clone())
+                        .data(access.clone())
+                        .data(reverse_index.clone())
+                        .data(notifier.clone())
+                        .data(web::PayloadConfig::new(PAYLOAD_LIMIT))
+                        .route("/api/v1/changes", web::get().to_async(get_changes))
+                        .route("/api/v1/changes/stream", web::get().to(stream_changes))
                         .service(api_scope())
                         .service(index_scope(reg.index_path()))
-                        .service(crates_scope(reg.crates_path())),
-                )
-                .default_service(
-                    web::resource("")
-                        .route(web::get().to(return_404))
-                        // all requests that are not `GET`
-                        .route(
-                            web::route()
-                                .guard(guard::Not(guard::Get()))
-                                .to(HttpResponse::MethodNotAllowed),
-                        ),
-                )
+                        .service(crates_scope(reg)),
+                );
+            }
+
+            app.default_service(
+                web::resource("")
+                    .route(web::get().to(return_404))
+                    // all requests that are not `GET`
+                    .route(
+                        web::route()
+                            .guard(guard::Not(guard::Get()))
+                            .to(HttpResponse::MethodNotAllowed),
+                    ),
+            )
         });
 
-        let addr = SocketAddr::new(config.ip(), config.port());
+        let addr = SocketAddr::new(http.ip(), http.port());
 
-        if config.ssl() {
-            server
-                .bind_rustls(addr, config.build_ssl_config()?)?
-                .start();
+        if http.ssl() {
+            server.bind_rustls(addr, http.build_ssl_config()?)?.start();
         } else {
             server.bind(addr)?.start();
         };
 
-        write_config_json(&registry).and_then(|o| {
-            if let Some(oid) = o {
-                info!(
-                    "Custom url(dl and api) has been written to config.json.(commid id: {})",
-                    oid
-                );
-            }
+        for registry in &registries {
+            let _lock = registry.lock("serve")?;
+            write_config_json(registry).and_then(|o| {
+                if let Some(oid) = o {
+                    info!(
+                        "Custom url(dl and api) of '{}' has been written to config.json.(commid id: {})",
+                        registry.config().name(),
+                        oid
+                    );
+                }
 
-            Ok(o)
-        })?;
+                Ok(o)
+            })?;
+        }
+
+        watch_reload(self.config.clone(), accesses)?;
 
         info!("Registry server started.");
-        info!(
-            "Users need to add this source to Cargo's configuration => {}/index",
-            registry.base_url()
-        );
+        for registry in &registries {
+            info!(
+                "Users need to add this source to Cargo's configuration => {}/index",
+                registry.base_url()
+            );
+        }
 
         sys.run()?;
         Ok(())
     }
 }
 
+/// Access control of a served registry, which is shared by all workers and read from the
+/// config files again on `SIGHUP`
+#[derive(Debug, Clone)]
+struct Access {
+    name: String,
+    root: PathBuf,
+    control: Arc<RwLock<AccessControl>>,
+    replica: Option<ReplicaConfig>,
+}
+
+impl Access {
+    fn new(registry: &Registry) -> Self {
+        Access {
+            name: registry.config().name().to_owned(),
+            root: registry.root().to_owned(),
+            control: Arc::new(RwLock::new(registry.config().access().clone())),
+            replica: registry.replica_config().cloned(),
+        }
+    }
+
+    fn get(&self) -> AccessControl {
+        self.control
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn set(&self, control: AccessControl) {
+        *self.control.write().unwrap_or_else(|e| e.into_inner()) = control;
+    }
+
+    /// Reason of refusing writes in the read-only or maintenance mode, `None` if writes
+    /// are allowed
+    ///
+    fn write_refusal(&self) -> Option<String> {
+        let control = self.get();
+        if control.maintenance() {
+            Some(format!(
+                "registry '{}' is under maintenance, please try again later",
+                self.name
+            ))
+        } else if control.read_only() {
+            Some(format!("registry '{}' is read-only", self.name))
+        } else {
+            None
+        }
+    }
+
+    /// Error response of the web API refusing a write, see `write_refusal`
+    ///
+    fn refuse_write(&self) -> Option<HttpResponse> {
+        self.write_refusal()
+            .map(|detail| api_error(HttpResponse::ServiceUnavailable(), detail))
+    }
+
+    /// Error response of the change feed for a request without an allowed token
+    ///
+    fn refuse_feed(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        let control = self.get();
+        if !control.feed_on() {
+            return Some(api_error(
+                HttpResponse::NotFound(),
+                format!("change feed of registry '{}' is not enabled", self.name),
+            ));
+        }
+
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim_start_matches("Bearer ").trim());
+        match token {
+            Some(token) if control.feed_allowed(token) => None,
+            Some(_) => Some(api_error(
+                HttpResponse::Forbidden(),
+                "the token is not allowed to read the change feed",
+            )),
+            None => Some(api_error(
+                HttpResponse::Unauthorized(),
+                "a token is required to read the change feed",
+            )),
+        }
+    }
+
+    /// Response of a write of the web API which is not handled by this registry, it is
+    /// refused by `refuse_write`, or refused or forwarded by a replica
+    ///
+    fn route_write(&self, request: &HttpRequest, body: web::Bytes) -> Option<AsyncResponse> {
+        if let Some(response) = self.refuse_write() {
+            return Some(Box::new(future::ok(response)));
+        }
+
+        let replica = self.replica.as_ref()?;
+        if replica.forward_writes {
+            return Some(Box::new(forward_write(
+                request,
+                body,
+                &self.name,
+                &replica.primary,
+            )));
+        }
+        Some(Box::new(future::ok(api_error(
+            HttpResponse::Forbidden(),
+            format!(
+                "registry '{}' is a replica, please publish to its primary {}",
+                self.name, replica.primary
+            ),
+        ))))
+    }
+}
+
+/// Response of a handler which waits for IO
+type AsyncResponse = Box<dyn Future<Item = HttpResponse, Error = SkrdError>>;
+
+/// Forward a write of the web API to the primary of a replica, with its token and body
+///
+fn forward_write(
+    request: &HttpRequest,
+    body: web::Bytes,
+    name: &str,
+    primary: &str,
+) -> impl Future<Item = HttpResponse, Error = SkrdError> {
+    // path in the scope of the registry, e.g. `/api/v1/crates/new`
+    let mut url = format!(
+        "{}{}",
+        primary,
+        request.path().get(name.len() + 1..).unwrap_or_default()
+    );
+    if !request.query_string().is_empty() {
+        url.push('?');
+        url.push_str(request.query_string());
+    }
+    let method = request.method().as_str().to_owned();
+    let headers: Vec<(String, String)> = [header::AUTHORIZATION, header::CONTENT_TYPE]
+        .iter()
+        .filter_map(|name| {
+            let value = request.headers().get(name)?.to_str().ok()?;
+            Some((name.as_str().to_owned(), value.to_owned()))
+        })
+        .collect();
+    let primary = primary.to_owned();
+
+    web::block(move || -> SkrdResult<_> {
+        let method = reqwest::Method::from_bytes(method.as_bytes())
+            .map_err(|e| SkrdError::Custom(e.to_string()))?;
+        let mut builder = reqwest::Client::new()
+            .request(method, &url)
+            .body(body.to_vec());
+        for (name, value) in &headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        let mut response = builder.send()?;
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let mut content = Vec::new();
+        response.copy_to(&mut content)?;
+        Ok((response.status().as_u16(), content_type, content))
+    })
+    .then(move |result| match result {
+        Ok((status, content_type, content)) => {
+            let mut response = HttpResponse::build(
+                StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY),
+            );
+            if let Some(content_type) = content_type {
+                response.content_type(content_type);
+            }
+            Ok(response.body(content))
+        }
+        Err(e) => {
+            let detail = match e {
+                BlockingError::Error(e) => e.to_string(),
+                BlockingError::Canceled => "forwarding is canceled".to_owned(),
+            };
+            warn!("Write forwarded to {} error: {}", primary, detail);
+            Ok(api_error(
+                HttpResponse::BadGateway(),
+                format!("primary {} is unavailable: {}", primary, detail),
+            ))
+        }
+    })
+}
+
+fn log_access(name: &str, control: &AccessControl) {
+    info!(
+        "Access Control of '{}' => git-receive-pack: {}, git-upload-pack: {}, read-only: {}, maintenance: {}, change feed: {}",
+        name,
+        control.receive_on(),
+        control.upload_on(),
+        control.read_only(),
+        control.maintenance(),
+        control.feed_on()
+    );
+}
+
+/// Read access control of the served registries again, from the server config if it is
+/// set, otherwise from `registry.toml`
+///
+fn reload_access(server_config: Option<&Path>, accesses: &[Access]) -> SkrdResult<()> {
+    let configs = match server_config {
+        Some(path) => ServeConfig::open(path)?
+            .1
+            .into_iter()
+            .map(|registry| registry.config().clone())
+            .collect(),
+        None => accesses
+            .iter()
+            .map(|access| RegistryConfig::open(access.root.join(Registry::REGISTRY_TOML_FILE)))
+            .collect::<SkrdResult<Vec<_>>>()?,
+    };
+
+    for access in accesses {
+        match configs.iter().find(|config| config.name() == access.name) {
+            Some(config) => {
+                access.set(config.access().clone());
+                log_access(&access.name, config.access());
+            }
+            None => warn!(
+                "Registry '{}' is not found, its access control is not changed.",
+                access.name
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reload access control on `SIGHUP`, so modes can be switched without restarting
+///
+#[cfg(unix)]
+fn watch_reload(server_config: Option<PathBuf>, accesses: Vec<Access>) -> SkrdResult<()> {
+    let signals = signal_hook::iterator::Signals::new([signal_hook::SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("SIGHUP received, reloading access control ...");
+            if let Err(e) = reload_access(server_config.as_deref(), &accesses) {
+                error!("Access control reload error: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn watch_reload(_server_config: Option<PathBuf>, _accesses: Vec<Access>) -> SkrdResult<()> {
+    Ok(())
+}
+
 fn api_scope() -> actix_web::Scope {
     web::scope("/api/v1/crates")
         .service(web::resource("").route(web::get().to(search)))
-        .route("/new", web::put().to(publish))
+        .route("/new", web::put().to_async(publish))
         .service(
             web::scope("/{name}")
+                .route("/diff/{from}/{to}", web::get().to_async(diff))
+                .route(
+                    "/reverse_dependencies",
+                    web::get().to_async(get_reverse_dependencies),
+                )
                 .service(
                     web::resource("/owners")
                         .route(web::get().to(get_owners))
-                        .route(web::put().to(add_owners))
-                        .route(web::delete().to(delete_owners)),
+                        .route(web::put().to_async(add_owners))
+                        .route(web::delete().to_async(delete_owners)),
                 )
                 .service(
                     web::scope("/{version}")
                         .route("/download", web::get().to(redirect_download))
-                        .route("/yank", web::put().to(yank))
-                        .route("/unyank", web::put().to(unyank)),
+                        .route("/source", web::get().to_async(browse_source_root))
+                        .route("/source/{path:.*}", web::get().to_async(browse_source))
+                        .route("/yank", web::put().to_async(yank))
+                        .route("/unyank", web::put().to_async(unyank)),
                 ),
         )
 }
@@ -163,28 +497,405 @@ fn index_scope<P: Into<PathBuf>>(index_path: P) -> actix_web::Scope {
         )
 }
 
-fn crates_scope<P: Into<PathBuf>>(crates_path: P) -> actix_web::Scope {
-    web::scope("/crates")
-        .service(actix_files::Files::new("/", crates_path.into()).show_files_listing())
+fn crates_scope(registry: &Registry) -> actix_web::Scope {
+    match registry.storage().local_path() {
+        Some(crates_path) => web::scope("/crates")
+            .service(actix_files::Files::new("/", crates_path).show_files_listing()),
+        None => web::scope("/crates").route("/{path:.*}", web::get().to(redirect_crate_file)),
+    }
 }
 
 // /api/v1/crates/tokio/0.1.21/download
 fn redirect_download(
     registry: web::Data<Registry>,
+    overlay: web::Data<Overlay>,
     path: web::Path<(String, String)>,
-) -> HttpResponse {
+) -> SkrdResult<Either<HttpResponse, actix_files::NamedFile>> {
     let name = &path.0;
     let version = &path.1;
+    let not_found = || {
+        Either::A(api_error(
+            HttpResponse::NotFound(),
+            format!("crate `{}-{}` is not found", name, version),
+        ))
+    };
 
-    let location = format!(
-        "/{}/crates/{}",
-        registry.config().name(),
-        get_crate_path(name, version)
-    );
+    // a virtual registry has no crate files, they are sent from the backing registry
+    let owner = if overlay.is_empty() {
+        &registry
+    } else {
+        match overlay.owner(name) {
+            Some(owner) => owner,
+            None => return Ok(not_found()),
+        }
+    };
+    // any casing of the name is redirected to the published one
+    let crate_path = match owner.find_crate(name, version)? {
+        Some(meta) => owner.crate_path(&meta),
+        None => return Ok(not_found()),
+    };
+
+    // remote storage
+    if let Some(url) = owner.storage().download_url(&crate_path)? {
+        return Ok(Either::A(
+            HttpResponse::Found().header(header::LOCATION, url).finish(),
+        ));
+    }
+
+    if !overlay.is_empty() {
+        let file = owner
+            .storage()
+            .local_path()
+            .map(|root| root.join(&crate_path))
+            .filter(|file| file.is_file());
+        return Ok(match file {
+            Some(file) => Either::B(actix_files::NamedFile::open(file)?),
+            None => not_found(),
+        });
+    }
+
+    let location = format!("/{}/crates/{}", registry.config().name(), crate_path);
+
+    Ok(Either::A(
+        HttpResponse::Found()
+            .header(header::LOCATION, location)
+            .finish(),
+    ))
+}
+
+// /crates/to/ki/tokio/tokio-0.1.21.crate of a registry with remote storage
+fn redirect_crate_file(
+    registry: web::Data<Registry>,
+    path: web::Path<String>,
+) -> SkrdResult<HttpResponse> {
+    match registry.storage().download_url(&path)? {
+        Some(url) => Ok(HttpResponse::Found().header(header::LOCATION, url).finish()),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Max size of a source file returned by the source browser
+const MAX_SOURCE_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+#[derive(Serialize)]
+struct SourceListing<'a> {
+    name: &'a str,
+    version: &'a str,
+    path: &'a str,
+    entries: Vec<SourceEntry>,
+}
+
+// /api/v1/crates/tokio/0.1.21/source
+fn browse_source_root(
+    registry: web::Data<Registry>,
+    overlay: web::Data<Overlay>,
+    path: web::Path<(String, String)>,
+) -> impl Future<Item = HttpResponse, Error = SkrdError> {
+    let registry = overlay.owner(&path.0).unwrap_or(&registry).clone();
+    let (name, version) = path.into_inner();
+    open_archives(registry, name.clone(), vec![version.clone()])
+        .and_then(move |mut archives| send_source(archives.pop().flatten(), &name, &version, ""))
+}
+
+// /api/v1/crates/tokio/0.1.21/source/src/lib.rs
+fn browse_source(
+    registry: web::Data<Registry>,
+    overlay: web::Data<Overlay>,
+    path: web::Path<(String, String, String)>,
+) -> impl Future<Item = HttpResponse, Error = SkrdError> {
+    let registry = overlay.owner(&path.0).unwrap_or(&registry).clone();
+    let (name, version, source_path) = path.into_inner();
+    open_archives(registry, name.clone(), vec![version.clone()]).and_then(move |mut archives| {
+        send_source(archives.pop().flatten(), &name, &version, &source_path)
+    })
+}
+
+/// Open the crate files of `versions` on the thread pool, reading a remote storage blocks
+///
+fn open_archives(
+    registry: Registry,
+    name: String,
+    versions: Vec<String>,
+) -> impl Future<Item = Vec<Option<CrateArchive>>, Error = SkrdError> {
+    web::block(move || {
+        versions
+            .iter()
+            .map(|version| CrateArchive::open(&registry, &name, version))
+            .collect::<SkrdResult<Vec<_>>>()
+    })
+    .map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => SkrdError::StaticCustom("opening crate files is canceled"),
+    })
+}
+
+fn send_source(
+    archive: Option<CrateArchive>,
+    name: &str,
+    version: &str,
+    path: &str,
+) -> SkrdResult<HttpResponse> {
+    let source_path = match normalize_source_path(path) {
+        Ok(p) => p,
+        Err(e) => return Ok(api_error(HttpResponse::BadRequest(), e)),
+    };
+
+    let archive = match archive {
+        Some(archive) => archive,
+        None => {
+            return Ok(api_error(
+                HttpResponse::NotFound(),
+                format!("crate `{}-{}` is not found", name, version),
+            ))
+        }
+    };
+
+    match archive.lookup(&source_path, MAX_SOURCE_FILE_SIZE)? {
+        Some(SourceNode::Dir(entries)) => Ok(HttpResponse::Ok().json(SourceListing {
+            name: archive.name(),
+            version: archive.version(),
+            path: &source_path,
+            entries,
+        })),
+        Some(SourceNode::File(SourceFile {
+            content: Some(content),
+            ..
+        })) => {
+            // contents of crates are untrusted, browsers never render them on this origin
+            let mut response = HttpResponse::Ok();
+            response
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .header(header::CONTENT_SECURITY_POLICY, "sandbox");
+            if std::str::from_utf8(&content).is_ok() {
+                response.content_type(mime::TEXT_PLAIN_UTF_8.to_string());
+            } else {
+                let file_name = source_path.rsplit('/').next().unwrap_or(&source_path);
+                response
+                    .content_type(mime::APPLICATION_OCTET_STREAM.to_string())
+                    .set(header::ContentDisposition {
+                        disposition: header::DispositionType::Attachment,
+                        parameters: vec![header::DispositionParam::Filename(file_name.to_owned())],
+                    });
+            }
+            Ok(response.body(content))
+        }
+        Some(SourceNode::File(SourceFile {
+            size,
+            content: None,
+            ..
+        })) => Ok(api_error(
+            HttpResponse::PayloadTooLarge(),
+            format!(
+                "`{}` is too large to browse ({} bytes, limit {} bytes)",
+                source_path, size, MAX_SOURCE_FILE_SIZE
+            ),
+        )),
+        None => Ok(api_error(
+            HttpResponse::NotFound(),
+            format!(
+                "`{}` is not found in crate `{}-{}`",
+                source_path, name, version
+            ),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    #[serde(default)]
+    summary: bool,
+}
+
+// /api/v1/crates/tokio/diff/0.1.20/0.1.21
+// /api/v1/crates/tokio/diff/0.1.20/0.1.21?summary=true
+fn diff(
+    registry: web::Data<Registry>,
+    overlay: web::Data<Overlay>,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<DiffQuery>,
+) -> impl Future<Item = HttpResponse, Error = SkrdError> {
+    let registry = overlay.owner(&path.0).unwrap_or(&registry).clone();
+    let (name, from, to) = path.into_inner();
+    open_archives(registry, name.clone(), vec![from.clone(), to.clone()])
+        .and_then(move |archives| send_diff(archives, &name, &[&from, &to], query.summary))
+}
+
+fn send_diff(
+    opened: Vec<Option<CrateArchive>>,
+    name: &str,
+    versions: &[&str],
+    summary: bool,
+) -> SkrdResult<HttpResponse> {
+    let mut archives = Vec::with_capacity(2);
+    for (archive, version) in opened.into_iter().zip(versions) {
+        match archive {
+            Some(archive) => archives.push(archive),
+            None => {
+                return Ok(api_error(
+                    HttpResponse::NotFound(),
+                    format!("crate `{}-{}` is not found", name, version),
+                ))
+            }
+        }
+    }
 
-    HttpResponse::Found()
-        .header(header::LOCATION, location)
-        .finish()
+    let crate_diff = CrateDiff::new(&archives[0], &archives[1])?;
+
+    if summary {
+        Ok(HttpResponse::Ok().json(crate_diff))
+    } else {
+        Ok(HttpResponse::Ok()
+            .content_type("text/x-diff; charset=utf-8")
+            .body(crate_diff.patch()))
+    }
+}
+
+#[derive(Serialize)]
+struct ReverseDependencies {
+    dependencies: Vec<ReverseDependency>,
+    meta: ReverseDependenciesMeta,
+}
+
+#[derive(Serialize)]
+struct ReverseDependenciesMeta {
+    total: usize,
+}
+
+// /api/v1/crates/tokio/reverse_dependencies
+fn get_reverse_dependencies(
+    registry: web::Data<Registry>,
+    reverse_index: web::Data<ReverseIndexCache>,
+    name: web::Path<String>,
+) -> impl Future<Item = HttpResponse, Error = SkrdError> {
+    let registry = registry.get_ref().clone();
+    let reverse_index = reverse_index.get_ref().clone();
+    let name = name.into_inner();
+
+    // the index is read again on the thread pool after it is changed
+    web::block(move || {
+        reverse_index
+            .get(&registry)?
+            .dependents(&name)
+            .map(|d| (name, d))
+    })
+    .map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => {
+            SkrdError::StaticCustom("finding reverse dependencies is canceled")
+        }
+    })
+    .map(|(name, dependencies)| match dependencies {
+        Some(dependencies) => HttpResponse::Ok().json(ReverseDependencies {
+            meta: ReverseDependenciesMeta {
+                total: dependencies.len(),
+            },
+            dependencies,
+        }),
+        None => api_error(
+            HttpResponse::NotFound(),
+            format!("crate `{}` is not found", name),
+        ),
+    })
+}
+
+#[derive(Deserialize)]
+struct ChangeQuery {
+    since: Option<String>,
+    #[serde(default)]
+    wait: u64,
+}
+
+// /api/v1/changes?since=<commit>&wait=60
+// waits at most `wait` seconds if there is no change since the commit
+fn get_changes(
+    request: HttpRequest,
+    registry: web::Data<Registry>,
+    access: web::Data<Access>,
+    notifier: web::Data<ChangeNotifier>,
+    query: web::Query<ChangeQuery>,
+) -> AsyncResponse {
+    if let Some(response) = access.refuse_feed(&request) {
+        return Box::new(future::ok(response));
+    }
+
+    let index_path = registry.index_path().clone();
+    let notifier = notifier.get_ref().clone();
+    let query = query.into_inner();
+    let wait = query.wait.min(FEED_WAIT);
+    let deadline = Instant::now() + Duration::from_secs(wait);
+
+    Box::new(
+        read_page(index_path.clone(), query.since.clone())
+            .and_then(move |page| -> PageFuture {
+                if wait == 0 || query.since.as_deref() != Some(page.cursor.as_str()) {
+                    return Box::new(future::ok(page));
+                }
+                // the notifier wakes it up, no thread is held while waiting
+                match notifier.wait(page.cursor, deadline) {
+                    Ok(receiver) => {
+                        Box::new(receiver.then(move |_| read_page(index_path, query.since)))
+                    }
+                    Err(e) => Box::new(future::err(e)),
+                }
+            })
+            .map(|page| HttpResponse::Ok().no_cache().json(page)),
+    )
+}
+
+type PageFuture = Box<dyn Future<Item = ChangePage, Error = SkrdError>>;
+
+fn read_page(index_path: PathBuf, since: Option<String>) -> PageFuture {
+    Box::new(
+        web::block(move || read_changes(&index_path, since.as_deref())).map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => SkrdError::StaticCustom("reading changes is canceled"),
+        }),
+    )
+}
+
+// /api/v1/changes/stream?since=<commit>
+// Server-Sent Events of the changes, `Last-Event-ID` is the cursor if `since` is not set
+fn stream_changes(
+    request: HttpRequest,
+    registry: web::Data<Registry>,
+    access: web::Data<Access>,
+    notifier: web::Data<ChangeNotifier>,
+    query: web::Query<ChangeQuery>,
+) -> SkrdResult<HttpResponse> {
+    if let Some(response) = access.refuse_feed(&request) {
+        return Ok(response);
+    }
+
+    let since = query.into_inner().since.or_else(|| {
+        request
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    });
+    // the notifier sends the events of all streams of the registry
+    let receiver = match notifier.stream(since)? {
+        Some(receiver) => receiver,
+        None => {
+            return Ok(api_error(
+                HttpResponse::ServiceUnavailable(),
+                format!(
+                    "registry '{}' has {} change streams open, try again later",
+                    registry.config().name(),
+                    MAX_STREAMS
+                ),
+            ))
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .no_cache()
+        .content_type("text/event-stream")
+        .streaming(
+            receiver
+                .map(web::Bytes::from)
+                .map_err(|_| SkrdError::StaticCustom("change stream is closed")),
+        ))
 }
 
 /// 404 handler
@@ -196,12 +907,13 @@ fn git_upload_pack(
     request: HttpRequest,
     body: web::Bytes,
     registry: web::Data<Registry>,
+    access: web::Data<Access>,
 ) -> SkrdResult<HttpResponse> {
     if request.content_type() != "application/x-git-upload-pack-request" {
         return Ok(HttpResponse::Forbidden().finish());
     }
 
-    if !registry.config().upload_on() {
+    if !access.get().upload_on() {
         return Ok(HttpResponse::Forbidden().finish());
     }
 
@@ -227,8 +939,17 @@ fn git_upload_pack(
         .body(output.stdout))
 }
 
-// TODO: git_receive_pack
-fn git_receive_pack(request: HttpRequest) -> SkrdResult<HttpResponse> {
+// TODO: git_receive_pack, which should hold the registry lock while the index is written
+fn git_receive_pack(request: HttpRequest, access: web::Data<Access>) -> SkrdResult<HttpResponse> {
+    if let Some(detail) = access.write_refusal() {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .content_type(mime::TEXT_PLAIN_UTF_8.to_string())
+            .body(detail));
+    }
+    if !access.get().receive_on() || access.replica.is_some() {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
     info!("{:?}", request);
     Ok(HttpResponse::Ok()
         .content_type("application/x-git-receive-pack-result")
@@ -236,16 +957,31 @@ fn git_receive_pack(request: HttpRequest) -> SkrdResult<HttpResponse> {
 }
 
 // http://localhost:9090/crates.io-index/info/refs?service=git-upload-pack
-fn get_info_refs(request: HttpRequest, registry: web::Data<Registry>) -> SkrdResult<HttpResponse> {
+fn get_info_refs(
+    request: HttpRequest,
+    registry: web::Data<Registry>,
+    access: web::Data<Access>,
+) -> SkrdResult<HttpResponse> {
     match get_service_from_query_string(request.query_string()) {
         Some(service) => {
             let is_upload_pack = service == "upload-pack";
             let is_receive_pack = service == "receive-pack";
 
+            // pushes are refused in the read-only and maintenance modes
+            if is_receive_pack {
+                if let Some(detail) = access.write_refusal() {
+                    return Ok(HttpResponse::ServiceUnavailable()
+                        .no_cache()
+                        .content_type(mime::TEXT_PLAIN_UTF_8.to_string())
+                        .body(detail));
+                }
+            }
+
             // access control
+            let control = access.get();
             if (!is_upload_pack && !is_receive_pack) // from query string
-                || (is_upload_pack && !registry.config().upload_on()) // from registry config(.toml)
-                || (is_receive_pack && !registry.config().receive_on())
+                || (is_upload_pack && !control.upload_on()) // from registry config(.toml)
+                || (is_receive_pack && !control.receive_on())
             {
                 return Ok(HttpResponse::Ok()
                     .no_cache()
@@ -421,28 +1157,112 @@ fn send_file_with_custom_mime<P: AsRef<Path>>(
         .use_last_modified(true))
 }
 
-fn publish() -> SkrdResult<impl Responder> {
-    Ok(HttpResponse::Ok().finish())
+/// Metadata of `cargo publish`, only the name is read for now
+#[derive(Deserialize)]
+struct PublishMeta {
+    name: String,
+}
+
+/// Read the metadata of a publish request: a JSON length (u32 little endian), the JSON,
+/// then the crate file
+///
+fn read_publish_meta(body: &[u8]) -> Option<PublishMeta> {
+    if body.len() < 4 {
+        return None;
+    }
+    let len = u32::from_le_bytes([body[0], body[1], body[2], body[3]]) as usize;
+    let json = body.get(4..len.checked_add(4)?)?;
+    serde_json::from_slice(json).ok()
+}
+
+// publishing is not implemented yet, only names are checked
+fn publish(
+    request: HttpRequest,
+    body: web::Bytes,
+    access: web::Data<Access>,
+    registry: web::Data<Registry>,
+) -> AsyncResponse {
+    if let Some(response) = access.route_write(&request, body.clone()) {
+        return response;
+    }
+
+    let name = match read_publish_meta(&body) {
+        Some(meta) => meta.name,
+        None => {
+            return Box::new(future::ok(api_error(
+                HttpResponse::BadRequest(),
+                "invalid publish request",
+            )))
+        }
+    };
+    if !is_valid_crate_name(&name) {
+        return Box::new(future::ok(api_error(
+            HttpResponse::BadRequest(),
+            format!("invalid crate name `{}`", name),
+        )));
+    }
+
+    let registry = registry.get_ref().clone();
+    Box::new(
+        web::block(move || {
+            registry
+                .find_versions(&name)
+                .map(|versions| (name, versions))
+        })
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => SkrdError::StaticCustom("publishing is canceled"),
+        })
+        .map(|(name, versions)| {
+            // crate names are case-insensitive, a case variant can not take another crate
+            match versions.iter().find(|meta| meta.name != name) {
+                Some(existing) => api_error(
+                    HttpResponse::BadRequest(),
+                    format!(
+                        "crate name `{}` is taken by `{}`, crate names are case-insensitive",
+                        name, existing.name
+                    ),
+                ),
+                None => HttpResponse::Ok().finish(),
+            }
+        }),
+    )
 }
 
 fn get_owners() -> SkrdResult<impl Responder> {
     Ok(HttpResponse::Ok().finish())
 }
 
-fn add_owners() -> SkrdResult<impl Responder> {
-    Ok(HttpResponse::Ok().finish())
+fn add_owners(request: HttpRequest, body: web::Bytes, access: web::Data<Access>) -> AsyncResponse {
+    if let Some(response) = access.route_write(&request, body) {
+        return response;
+    }
+    Box::new(future::ok(HttpResponse::Ok().finish()))
 }
 
-fn delete_owners() -> SkrdResult<impl Responder> {
-    Ok(HttpResponse::Ok().finish())
+fn delete_owners(
+    request: HttpRequest,
+    body: web::Bytes,
+    access: web::Data<Access>,
+) -> AsyncResponse {
+    if let Some(response) = access.route_write(&request, body) {
+        return response;
+    }
+    Box::new(future::ok(HttpResponse::Ok().finish()))
 }
 
-fn yank() -> SkrdResult<impl Responder> {
-    Ok(HttpResponse::Ok().finish())
+fn yank(request: HttpRequest, body: web::Bytes, access: web::Data<Access>) -> AsyncResponse {
+    if let Some(response) = access.route_write(&request, body) {
+        return response;
+    }
+    Box::new(future::ok(HttpResponse::Ok().finish()))
 }
 
-fn unyank() -> SkrdResult<impl Responder> {
-    Ok(HttpResponse::Ok().finish())
+fn unyank(request: HttpRequest, body: web::Bytes, access: web::Data<Access>) -> AsyncResponse {
+    if let Some(response) = access.route_write(&request, body) {
+        return response;
+    }
+    Box::new(future::ok(HttpResponse::Ok().finish()))
 }
 
 fn search() -> SkrdResult<impl Responder> {
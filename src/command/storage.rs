@@ -0,0 +1,170 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::registry::Registry;
+use crate::storage::{link_blobs, move_crate_files, Backend, ContentAddressedConfig, Layout};
+use crate::util::write_config_json;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub enum Storage {
+    /// Convert the crates directory in place to content-addressed storage
+    #[structopt(name = "content-addressed")]
+    ContentAddressed(ContentAddressed),
+
+    /// Move crate files to another layout
+    #[structopt(name = "layout")]
+    Layout(MoveLayout),
+}
+
+impl Storage {
+    pub fn storage(self) -> SkrdResult<()> {
+        match self {
+            Storage::ContentAddressed(command) => command.convert(),
+            Storage::Layout(command) => command.migrate(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ContentAddressed {
+    #[structopt(
+        long = "blobs",
+        help = "Set the blobs directory, which can be shared by registries on the same filesystem",
+        value_name = "BLOBS PATH"
+    )]
+    pub blobs: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl ContentAddressed {
+    pub fn convert(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let mut registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+        let _lock = registry.lock("storage content-addressed")?;
+
+        std::fs::create_dir_all(&self.blobs)?;
+        let blobs = self.blobs.canonicalize()?;
+
+        match &registry.config().storage().backend {
+            Backend::Filesystem => {}
+            Backend::ContentAddressed(config)
+                if registry.root().join(&config.blobs).canonicalize().ok()
+                    == Some(blobs.clone()) => {}
+            _ => {
+                return Err(SkrdError::Custom(format!(
+                    "registry '{}' does not use filesystem storage or these blobs",
+                    registry.config().name()
+                )))
+            }
+        }
+
+        info!(
+            "Start to link crate files of '{}' to {} ...",
+            registry.config().name(),
+            blobs.display()
+        );
+
+        let report = link_blobs(&registry, &blobs)?;
+        info!(
+            "Total: {} blobs stored, {} files deduplicated ({} bytes), {} unchanged, {} failed.",
+            report.stored, report.deduplicated, report.bytes, report.unchanged, report.failed
+        );
+
+        if report.failed > 0 {
+            return Err(SkrdError::Custom(format!(
+                "{} crate files are not linked, registry.toml is not changed",
+                report.failed
+            )));
+        }
+
+        if registry.config().storage().is_filesystem() {
+            let mut storage = registry.config().storage().clone();
+            storage.backend = Backend::ContentAddressed(ContentAddressedConfig { blobs });
+            registry.config_mut().set_storage(storage);
+            registry.save_config()?;
+            info!("Storage of registry.toml is set to content-addressed.");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MoveLayout {
+    #[structopt(
+        help = "Set the new layout",
+        value_name = "prefix|flat|checksum",
+        parse(try_from_str)
+    )]
+    pub layout: Layout,
+
+    #[structopt(
+        help = "Set the registry path",
+        value_name = "REGISTRY PATH",
+        parse(try_from_str)
+    )]
+    pub registry: Option<Registry>,
+}
+
+impl MoveLayout {
+    pub fn migrate(self) -> SkrdResult<()> {
+        // if registry is not specified, try current directory
+        let mut registry = if let Some(registry) = self.registry {
+            registry
+        } else {
+            let current_dir = std::env::current_dir()?;
+            Registry::open(current_dir)?
+        };
+        let _lock = registry.lock("storage layout")?;
+
+        if registry.virtual_config().is_some() {
+            return Err(SkrdError::StaticCustom(
+                "a virtual registry has no crate files",
+            ));
+        }
+
+        let from = registry.layout();
+        info!(
+            "Start to move crate files of '{}' from {} to {} layout ...",
+            registry.config().name(),
+            from,
+            self.layout
+        );
+
+        let report = move_crate_files(&registry, from, self.layout)?;
+        info!(
+            "Total: {} moved, {} unchanged, {} missing, {} failed.",
+            report.moved, report.unchanged, report.missing, report.failed
+        );
+
+        if report.failed > 0 {
+            return Err(SkrdError::Custom(format!(
+                "{} crate files are not moved, registry.toml is not changed",
+                report.failed
+            )));
+        }
+
+        let mut storage = registry.config().storage().clone();
+        storage.layout = self.layout;
+        registry.config_mut().set_storage(storage);
+        registry.save_config()?;
+        info!("Layout of registry.toml is set to {}.", self.layout);
+
+        if let Some(oid) = write_config_json(&registry)? {
+            info!("dl of config.json has been updated.(commit id: {})", oid);
+        }
+
+        Ok(())
+    }
+}
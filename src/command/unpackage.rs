@@ -0,0 +1,53 @@
+use crate::bundle::import_bundle;
+use crate::error::SkrdResult;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Unpackage {
+    #[structopt(
+        help = "Set the bundle path, without the volume number",
+        value_name = "BUNDLE PATH"
+    )]
+    bundle: PathBuf,
+
+    #[structopt(
+        help = "Set the registry path, a new registry is created if it does not exist",
+        value_name = "REGISTRY PATH"
+    )]
+    path: PathBuf,
+}
+
+impl Unpackage {
+    pub fn unpackage(self) -> SkrdResult<()> {
+        info!(
+            "Start to unpack {} into {} ...",
+            self.bundle.display(),
+            self.path.display()
+        );
+
+        let report = import_bundle(&self.bundle, &self.path)?;
+
+        if report.created {
+            info!("Registry {} is created.", self.path.display());
+        }
+
+        match report.index {
+            Some((Some(old), new)) => info!("Index is updated: {}..{}", old, new),
+            Some((None, new)) => info!("Index is imported at {}.", new),
+            None => info!("Index is up to date."),
+        }
+
+        info!(
+            "Total: {} crates imported, {} already exist, {} conflicts.",
+            report.crates.len(),
+            report.existing,
+            report.conflicts.len()
+        );
+        for conflict in &report.conflicts {
+            warn!("Conflict: {}", conflict);
+        }
+
+        Ok(())
+    }
+}
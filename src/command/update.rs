@@ -1,18 +1,28 @@
+use crate::command::replica::log_sync;
+use crate::command::virtual_registry::log_merge;
+use crate::overlay::merge_index;
+use crate::replica::{follow, sync};
 use crate::{
     error::{SkrdError, SkrdResult},
     registry::Registry,
-    util::download_crates,
+    util::{download_crates, write_config_json},
 };
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 pub struct Update {
+    #[structopt(
+        long = "follow",
+        help = "Keep following the change feed of the primary, only for replicas"
+    )]
+    pub follow: bool,
+
     #[structopt(
         help = "Set the registry path",
         value_name = "REGISTRY PATH",
         parse(try_from_str)
     )]
-    registry: Option<Registry>,
+    pub registry: Option<Registry>,
 }
 
 impl Update {
@@ -25,6 +35,38 @@ impl Update {
             Registry::open(current_dir)?
         };
 
+        // a replica pulls the index and crate files of its primary
+        if let Some(replica) = registry.replica_config() {
+            info!(
+                "Start to synchronize replica '{}' with {} ...",
+                registry.config().name(),
+                replica.primary
+            );
+            if self.follow {
+                return follow(&registry, log_sync);
+            }
+            let _lock = registry.lock("update")?;
+            let client = reqwest::ClientBuilder::new().gzip(false).build()?;
+            log_sync(&sync(&registry, &client)?);
+            return Ok(());
+        }
+        if self.follow {
+            return Err(SkrdError::StaticCustom(
+                "only replicas can follow the change feed of a primary",
+            ));
+        }
+        let _lock = registry.lock("update")?;
+
+        // the index of a virtual registry is merged again from its backing registries
+        if registry.virtual_config().is_some() {
+            info!(
+                "Start to merge virtual registry '{}' ...",
+                registry.config().name()
+            );
+            log_merge(&merge_index(&registry)?);
+            return Ok(());
+        }
+
         info!("Start to update mirror '{}' ...", registry.config().name());
 
         let repo = git2::Repository::open(registry.index_path())?;
@@ -41,6 +83,10 @@ impl Update {
         drop(repo);
         info!("Index is synchronized.");
 
+        // crate files stored by earlier versions are moved before they are checked
+        if let Some(oid) = write_config_json(&registry)? {
+            info!("dl of config.json has been updated.(commit id: {})", oid);
+        }
         download_crates(&registry)?;
 
         Ok(())
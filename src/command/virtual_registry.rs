@@ -0,0 +1,88 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::commit_index;
+use crate::overlay::{merge_index, MergeReport};
+use crate::registry::{Registry, UrlConfig, Virtual};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct VirtualRegistry {
+    #[structopt(
+        long = "name",
+        help = "Set the registry name",
+        value_name = "REGISTRY NAME"
+    )]
+    pub name: Option<String>,
+
+    #[structopt(help = "Set the registry path", value_name = "path")]
+    pub path: PathBuf,
+
+    #[structopt(
+        help = "Set the backing registries, crates in the first ones take priority",
+        value_name = "BACKING REGISTRY PATH",
+        required = true
+    )]
+    pub registries: Vec<PathBuf>,
+}
+
+impl VirtualRegistry {
+    pub fn create(self) -> SkrdResult<()> {
+        let name = if let Some(name) = &self.name {
+            name.clone()
+        } else {
+            self.path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| SkrdError::StaticCustom("the registry path provided is invalid"))?
+                .to_owned()
+        };
+
+        // backing registries are saved as absolute paths, so they do not depend on `path`
+        let mut registries = Vec::with_capacity(self.registries.len());
+        for path in &self.registries {
+            registries.push(path.canonicalize()?);
+        }
+        let backing = Virtual { registries };
+        backing.open_registries(&self.path)?;
+
+        let registry = Registry::create_virtual(&self.path, &name, backing)?;
+
+        git2::Repository::init(registry.index_path())?;
+        let content = serde_json::to_string_pretty(&UrlConfig::from(&registry))?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(registry.index_path().join(Registry::CONFIG_JSON_FILE))?;
+        file.write_all(content.as_bytes())?;
+        drop(file);
+        commit_index(&registry, "base_url")?;
+
+        info!("Start to merge index ...");
+        let _lock = registry.lock("virtual")?;
+        log_merge(&merge_index(&registry)?);
+
+        info!("Virtual registry is created.");
+        Ok(())
+    }
+}
+
+/// Log the result of `merge_index`
+///
+pub fn log_merge(report: &MergeReport) {
+    for collision in &report.collisions {
+        warn!("Name collision: {}.", collision);
+    }
+    match report.commit {
+        Some(oid) => info!("Index is merged. (commit id: {})", oid),
+        None => info!("Index is up to date."),
+    }
+    info!(
+        "Total: {} index files, {} updated, {} removed, {} name collisions.",
+        report.files,
+        report.updated,
+        report.removed,
+        report.collisions.len()
+    );
+}
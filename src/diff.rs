@@ -0,0 +1,196 @@
+use crate::archive::{CrateArchive, SourceFile};
+use crate::error::SkrdResult;
+use serde_derive::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Max size of a source file that will be diffed as text
+pub const MAX_DIFF_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Max total size of the source files of both versions read for a diff
+pub const MAX_DIFF_TOTAL_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Max number of files compared in a diff
+pub const MAX_DIFF_FILES: usize = 5000;
+
+/// Differences between the sources of two versions of a crate
+#[derive(Serialize, Debug)]
+pub struct CrateDiff {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    pub files: Vec<FileDiff>,
+    /// Number of files not compared because the diff exceeds `MAX_DIFF_TOTAL_SIZE` or
+    /// `MAX_DIFF_FILES`, the files are compared in the order of paths
+    pub omitted: usize,
+}
+
+/// Differences of a single file
+#[derive(Serialize, Debug)]
+pub struct FileDiff {
+    pub path: String,
+    pub status: FileStatus,
+    /// Binary or too large files are not diffed line by line
+    pub binary: bool,
+    pub insertions: usize,
+    pub deletions: usize,
+    #[serde(skip)]
+    pub patch: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl CrateDiff {
+    /// Compare two versions of a crate, unchanged files are omitted
+    ///
+    /// The diff is truncated if the files to read exceed the limits.
+    ///
+    pub fn new(from: &CrateArchive, to: &CrateArchive) -> SkrdResult<Self> {
+        let old_sizes = from.file_sizes()?;
+        let new_sizes = to.file_sizes()?;
+
+        let all_paths: BTreeSet<&String> = old_sizes.keys().chain(new_sizes.keys()).collect();
+        let mut paths = BTreeSet::new();
+        let mut total = 0;
+        for path in &all_paths {
+            // files larger than `MAX_DIFF_FILE_SIZE` are not read
+            let size: u64 = old_sizes
+                .get(*path)
+                .into_iter()
+                .chain(new_sizes.get(*path))
+                .filter(|size| **size <= MAX_DIFF_FILE_SIZE)
+                .sum();
+            if paths.len() == MAX_DIFF_FILES || total + size > MAX_DIFF_TOTAL_SIZE {
+                break;
+            }
+            total += size;
+            paths.insert((*path).clone());
+        }
+        let omitted = all_paths.len() - paths.len();
+
+        let old_files = from.files(&paths, MAX_DIFF_FILE_SIZE)?;
+        let new_files = to.files(&paths, MAX_DIFF_FILE_SIZE)?;
+
+        let mut files = Vec::new();
+        for path in &paths {
+            let old = old_files.get(path);
+            let new = new_files.get(path);
+
+            let status = match (old, new) {
+                (None, Some(_)) => FileStatus::Added,
+                (Some(_), None) => FileStatus::Removed,
+                (Some(old), Some(new)) if is_same(old, new) => continue,
+                _ => FileStatus::Modified,
+            };
+
+            let old_text = old.map(text_of);
+            let new_text = new.map(text_of);
+
+            // `None` means the file is absent, `Some(None)` means it is binary
+            let (old_text, new_text) = match (old_text, new_text) {
+                (Some(None), _) | (_, Some(None)) => {
+                    files.push(FileDiff {
+                        patch: format!(
+                            "diff --git a/{0} b/{0}\nBinary files {1} and {2} differ\n",
+                            path,
+                            old.map_or("/dev/null".to_owned(), |_| format!("a/{}", path)),
+                            new.map_or("/dev/null".to_owned(), |_| format!("b/{}", path)),
+                        ),
+                        path: path.clone(),
+                        status,
+                        binary: true,
+                        insertions: 0,
+                        deletions: 0,
+                    });
+                    continue;
+                }
+                (old_text, new_text) => (
+                    old_text.and_then(|t| t).unwrap_or(""),
+                    new_text.and_then(|t| t).unwrap_or(""),
+                ),
+            };
+
+            let text_diff = TextDiff::from_lines(old_text, new_text);
+
+            let mut insertions = 0;
+            let mut deletions = 0;
+            for change in text_diff.iter_all_changes() {
+                match change.tag() {
+                    ChangeTag::Insert => insertions += 1,
+                    ChangeTag::Delete => deletions += 1,
+                    ChangeTag::Equal => {}
+                }
+            }
+
+            let mut patch = format!("diff --git a/{0} b/{0}\n", path);
+            let _ = write!(
+                patch,
+                "{}",
+                text_diff.unified_diff().context_radius(3).header(
+                    &old.map_or("/dev/null".to_owned(), |_| format!("a/{}", path)),
+                    &new.map_or("/dev/null".to_owned(), |_| format!("b/{}", path)),
+                )
+            );
+
+            files.push(FileDiff {
+                path: path.clone(),
+                status,
+                binary: false,
+                insertions,
+                deletions,
+                patch,
+            });
+        }
+
+        Ok(CrateDiff {
+            name: from.name().to_owned(),
+            from: from.version().to_owned(),
+            to: to.version().to_owned(),
+            files,
+            omitted,
+        })
+    }
+
+    /// Unified diff of all changed files, with a note of the omitted files at the end
+    ///
+    pub fn patch(&self) -> String {
+        let mut patch: String = self.files.iter().map(|f| f.patch.as_str()).collect();
+        if self.omitted > 0 {
+            let _ = writeln!(
+                patch,
+                "diff truncated: {} files are not compared (limits: {} bytes, {} files)",
+                self.omitted, MAX_DIFF_TOTAL_SIZE, MAX_DIFF_FILES
+            );
+        }
+        patch
+    }
+}
+
+/// Files of the same size are the same if their contents, or SHA-256 of large files, are
+///
+fn is_same(old: &SourceFile, new: &SourceFile) -> bool {
+    if old.size != new.size {
+        return false;
+    }
+    match (&old.content, &new.content) {
+        (Some(old), Some(new)) => old == new,
+        _ => old.sha256.is_some() && old.sha256 == new.sha256,
+    }
+}
+
+/// Get the text of a source file, returns `None` if it is binary or too large
+///
+fn text_of(file: &SourceFile) -> Option<&str> {
+    let content = file.content.as_ref()?;
+    if content.iter().take(8000).any(|b| *b == 0) {
+        return None;
+    }
+    std::str::from_utf8(content).ok()
+}
@@ -26,7 +26,7 @@ pub enum SkrdError {
     TomlSerialize(toml::ser::Error),
 
     /// Json error
-    #[fail(display = "Toml error: {}", _0)]
+    #[fail(display = "Json error: {}", _0)]
     Json(serde_json::Error),
 
     /// Mime error
@@ -57,6 +57,10 @@ pub enum SkrdError {
     #[fail(display = "Reqwest error: {}", _0)]
     Reqwest(reqwest::Error),
 
+    /// Registry busy error, the registry is locked by another writer
+    #[fail(display = "Registry busy: {}", _0)]
+    Busy(String),
+
     /// Custom error
     #[fail(display = "Custom error: {}", _0)]
     Custom(String),
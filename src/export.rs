@@ -0,0 +1,318 @@
+use crate::bundle::file_sum;
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::{index_files, read_index_file};
+use crate::registry::{Registry, UrlConfig};
+use crate::storage::require_local;
+use crate::util::get_index_path;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, File};
+use std::io::Read;
+use std::path::Path;
+
+/// Result of `export_static`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StaticExport {
+    /// Index files written
+    pub index_files: usize,
+    /// Crate files copied
+    pub crates: usize,
+    /// Files already up to date
+    pub unchanged: usize,
+    /// Files removed because they are not in the registry anymore
+    pub removed: usize,
+    /// Versions in the index without crate files
+    pub missing: usize,
+}
+
+impl std::ops::Add for StaticExport {
+    type Output = StaticExport;
+
+    fn add(self, other: StaticExport) -> StaticExport {
+        StaticExport {
+            index_files: self.index_files + other.index_files,
+            crates: self.crates + other.crates,
+            unchanged: self.unchanged + other.unchanged,
+            removed: self.removed + other.removed,
+            missing: self.missing + other.missing,
+        }
+    }
+}
+
+/// Export `registry` to `out` as a static site of a sparse registry served at `url`
+///
+/// Directory structure:
+///
+/// out
+///   ├─index
+///   │  ├─config.json
+///   │  └─ ...
+///   └─crates
+///      └─ ...
+///
+/// The registry url for cargo is `sparse+{url}/index/`. Only changed files are written
+/// if `out` was exported before, and files not in the registry anymore are removed.
+///
+pub fn export_static(registry: &Registry, out: &Path, url: &str) -> SkrdResult<StaticExport> {
+    let crates_path = require_local(registry.storage(), "export-static")?;
+    let url = url.trim_end_matches('/');
+    let index_out = out.join(Registry::INDEX_DIRECTORY);
+    let crates_out = out.join(Registry::CRATES_DIRECTORY);
+    create_dir_all(&index_out)?;
+    create_dir_all(&crates_out)?;
+
+    let url_config = UrlConfig {
+        dl: format!(
+            "{}/{}/{}",
+            url,
+            Registry::CRATES_DIRECTORY,
+            registry.layout().dl_template()
+        ),
+        api: None,
+    };
+    let mut report = StaticExport::default();
+    if write_if_changed(
+        &index_out.join(Registry::CONFIG_JSON_FILE),
+        serde_json::to_string_pretty(&url_config)?.as_bytes(),
+    )? {
+        report.index_files += 1;
+    } else {
+        report.unchanged += 1;
+    }
+
+    report = report
+        + index_files(registry)
+            .par_bridge()
+            .map(|path| {
+                export_index_file(registry, crates_path, &path, out).unwrap_or_else(|e| {
+                    error!("Index file {} export error: {}", path.display(), e);
+                    StaticExport::default()
+                })
+            })
+            .reduce(StaticExport::default, |a, b| a + b);
+
+    report.removed += remove_stale(&index_out, registry.index_path())?;
+    report.removed += remove_stale(&crates_out, crates_path)?;
+
+    Ok(report)
+}
+
+fn export_index_file(
+    registry: &Registry,
+    crates_path: &Path,
+    path: &Path,
+    out: &Path,
+) -> SkrdResult<StaticExport> {
+    let mut report = StaticExport::default();
+
+    let relative = path
+        .strip_prefix(registry.index_path())
+        .map_err(|_| SkrdError::Custom(format!("{} is not in the index", path.display())))?;
+    let mut content = Vec::new();
+    File::open(path)?.read_to_end(&mut content)?;
+    if write_if_changed(
+        &out.join(Registry::INDEX_DIRECTORY).join(relative),
+        &content,
+    )? {
+        report.index_files += 1;
+    } else {
+        report.unchanged += 1;
+    }
+
+    for meta in read_index_file(path)? {
+        let crate_path = registry.crate_path(&meta);
+        let source = crates_path.join(&crate_path);
+        let target = out.join(Registry::CRATES_DIRECTORY).join(&crate_path);
+
+        if !source.is_file() {
+            report.missing += 1;
+            continue;
+        }
+
+        // crate files never change once published
+        if target.metadata().map(|m| m.len()).ok() == Some(source.metadata()?.len()) {
+            report.unchanged += 1;
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        std::fs::copy(&source, &target)?;
+        report.crates += 1;
+    }
+
+    Ok(report)
+}
+
+/// Write `content` to `path` if it is different, returns whether the file is written
+///
+pub fn write_if_changed(path: &Path, content: &[u8]) -> SkrdResult<bool> {
+    if let Ok(existing) = std::fs::read(path) {
+        if existing == content {
+            return Ok(false);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(true)
+}
+
+/// Remove the files in `out` which do not exist in `source`, returns the number of them
+///
+fn remove_stale(out: &Path, source: &Path) -> SkrdResult<usize> {
+    let mut removed = 0;
+    for entry in walkdir::WalkDir::new(out).min_depth(1).contents_first(true) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(out).unwrap_or(entry.path());
+
+        if entry.file_type().is_dir() {
+            if std::fs::read_dir(entry.path())?.next().is_none() {
+                std::fs::remove_dir(entry.path())?;
+            }
+        } else if relative != Path::new(Registry::CONFIG_JSON_FILE)
+            && !source.join(relative).is_file()
+        {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Crates selected to be exported, all crates are selected if it is empty
+#[derive(Debug, Default, Clone)]
+pub struct CrateSelection {
+    /// Crates of which all versions are selected
+    pub names: Vec<String>,
+    /// Selected versions, as `(name, version)`
+    pub versions: BTreeSet<(String, String)>,
+}
+
+impl CrateSelection {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.versions.is_empty()
+    }
+
+    pub fn contains(&self, name: &str, version: &str) -> bool {
+        self.is_empty()
+            || self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+            || self
+                .versions
+                .contains(&(name.to_owned(), version.to_owned()))
+    }
+
+    /// Select the registry packages locked in a `Cargo.lock`
+    ///
+    pub fn add_lockfile(&mut self, path: &Path) -> SkrdResult<()> {
+        #[derive(Deserialize)]
+        struct Lockfile {
+            #[serde(default)]
+            package: Vec<LockedPackage>,
+        }
+
+        #[derive(Deserialize)]
+        struct LockedPackage {
+            name: String,
+            version: String,
+            source: Option<String>,
+        }
+
+        let lockfile: Lockfile = toml::from_str(&std::fs::read_to_string(path)?)?;
+        for package in lockfile.package {
+            // path and git dependencies are not in registries
+            let from_registry = package.source.as_ref().is_some_and(|source| {
+                source.starts_with("registry+") || source.starts_with("sparse+")
+            });
+            if from_registry {
+                self.versions.insert((package.name, package.version));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Result of `export_local`
+#[derive(Debug, Default)]
+pub struct LocalExport {
+    pub versions: usize,
+    /// Crate files copied
+    pub crates: usize,
+    /// Selected versions which are not in the registry or have no crate files
+    pub missing: Vec<String>,
+}
+
+/// Export the selected crates of `registry` to `out` in the format of `cargo local-registry`
+///
+/// Directory structure:
+///
+/// out
+///   ├─index
+///   │  └─ ...
+///   ├─{name}-{version}.crate
+///   └─ ...
+///
+pub fn export_local(
+    registry: &Registry,
+    out: &Path,
+    selection: &CrateSelection,
+) -> SkrdResult<LocalExport> {
+    let crates_path = require_local(registry.storage(), "export-local")?;
+    create_dir_all(out.join(Registry::INDEX_DIRECTORY))?;
+
+    let mut report = LocalExport::default();
+    let mut found = BTreeSet::new();
+
+    for path in index_files(registry) {
+        let versions: Vec<_> = read_index_file(&path)?
+            .into_iter()
+            .filter(|meta| selection.contains(&meta.name, &meta.version))
+            .collect();
+        let name = match versions.first() {
+            Some(meta) => meta.name.clone(),
+            None => continue,
+        };
+
+        let mut lines = String::new();
+        for meta in versions {
+            found.insert((meta.name.clone(), meta.version.clone()));
+
+            let source = crates_path.join(registry.crate_path(&meta));
+            if !source.is_file() {
+                report.missing.push(meta.to_string());
+                continue;
+            }
+
+            let target = out.join(format!("{}-{}.crate", meta.name, meta.version));
+            if !target.is_file() || file_sum(&target)? != meta.checksum {
+                std::fs::copy(&source, &target)?;
+                report.crates += 1;
+            }
+
+            lines.push_str(&serde_json::to_string(&meta)?);
+            lines.push('\n');
+            report.versions += 1;
+        }
+
+        if !lines.is_empty() {
+            let index_file = out
+                .join(Registry::INDEX_DIRECTORY)
+                .join(get_index_path(&name));
+            write_if_changed(&index_file, lines.as_bytes())?;
+        }
+    }
+
+    for (name, version) in &selection.versions {
+        if !found.contains(&(name.clone(), version.clone())) {
+            report.missing.push(format!("{}-{}", name, version));
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,411 @@
+use crate::error::SkrdResult;
+use crate::index::{version_key, Change, ChangeAction};
+use crate::registry::{CrateMeta, Registry};
+use chrono::TimeZone;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
+use git2::{Commit, Oid, Repository, Sort};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Commits read at most for a page of changes
+pub const COMMIT_LIMIT: usize = 1000;
+
+/// Interval of checking the index `HEAD` for the waiting clients of the change feed
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval of comments sent in an idle change stream
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Change streams open at most for a registry
+pub const MAX_STREAMS: usize = 256;
+
+/// Changes of the index after a cursor, which is an index commit
+///
+/// `cursor` is the last commit read, clients ask for the next page since it. If the
+/// cursor asked for is not in the history of the index, e.g. the index is rebuilt,
+/// `reset` is true and clients should synchronize the whole registry.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangePage {
+    pub cursor: String,
+    pub changes: Vec<Change>,
+    /// More commits are after the cursor
+    #[serde(default)]
+    pub more: bool,
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Read the changes of the index at `index_path` after commit `since`
+///
+/// Without `since`, no change is returned, the cursor is the `HEAD` of the index.
+///
+pub fn read_changes(index_path: &Path, since: Option<&str>) -> SkrdResult<ChangePage> {
+    changes_after(&Repository::open(index_path)?, since)
+}
+
+/// Read the changes of `repo` after commit `since`, see `read_changes`
+///
+fn changes_after(repo: &Repository, since: Option<&str>) -> SkrdResult<ChangePage> {
+    let head = repo.head()?.peel_to_commit()?.id();
+
+    let mut page = ChangePage {
+        cursor: head.to_string(),
+        changes: Vec::new(),
+        more: false,
+        reset: false,
+    };
+    let since = match since {
+        Some(since) => since,
+        None => return Ok(page),
+    };
+    let since = match Oid::from_str(since) {
+        Ok(since) if is_ancestor(repo, since, head) => since,
+        _ => {
+            page.reset = true;
+            return Ok(page);
+        }
+    };
+
+    let (cursor, changes, more) = walk_changes(repo, since, head, Some(COMMIT_LIMIT))?;
+    page.cursor = cursor.to_string();
+    page.changes = changes;
+    page.more = more;
+    Ok(page)
+}
+
+/// Changes of the commits after `from` until `to` in `repo`
+///
+pub fn changes_between(repo: &Repository, from: Oid, to: Oid) -> SkrdResult<Vec<Change>> {
+    Ok(walk_changes(repo, from, to, None)?.1)
+}
+
+/// `ancestor` is `commit` or one of its ancestors
+///
+pub fn is_ancestor(repo: &Repository, ancestor: Oid, commit: Oid) -> bool {
+    ancestor == commit || repo.graph_descendant_of(commit, ancestor).unwrap_or(false)
+}
+
+/// Read the changes of at most `limit` commits after `since` until `head`, returns the
+/// last commit read and whether more commits are left
+///
+fn walk_changes(
+    repo: &Repository,
+    since: Oid,
+    head: Oid,
+    limit: Option<usize>,
+) -> SkrdResult<(Oid, Vec<Change>, bool)> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE);
+    walk.push(head)?;
+    walk.hide(since)?;
+
+    let mut cursor = since;
+    let mut changes = Vec::new();
+    for (i, oid) in walk.enumerate() {
+        if Some(i) == limit {
+            return Ok((cursor, changes, true));
+        }
+        let commit = repo.find_commit(oid?)?;
+        changes.extend(commit_changes(repo, &commit)?);
+        cursor = commit.id();
+    }
+
+    Ok((cursor, changes, false))
+}
+
+/// Changes of the index files in a commit, compared with its first parent
+///
+fn commit_changes(repo: &Repository, commit: &Commit) -> SkrdResult<Vec<Change>> {
+    let tree = commit.tree()?;
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+
+    let timestamp = chrono::Utc
+        .timestamp_opt(commit.time().seconds(), 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+
+    let mut changes = Vec::new();
+    for delta in diff.deltas() {
+        let path = match delta.new_file().path() {
+            Some(path) => path,
+            None => continue,
+        };
+        if path == Path::new(Registry::CONFIG_JSON_FILE) || delta.new_file().id().is_zero() {
+            continue;
+        }
+
+        // versions before the commit, by the versions without build metadata
+        let old: BTreeMap<String, bool> = read_blob(repo, delta.old_file().id())
+            .into_iter()
+            .map(|meta| (version_key(&meta.version).to_owned(), meta.yanked()))
+            .collect();
+
+        for meta in read_blob(repo, delta.new_file().id()) {
+            let action = match old.get(version_key(&meta.version)) {
+                None => ChangeAction::Published,
+                Some(false) if meta.yanked() => ChangeAction::Yanked,
+                Some(true) if !meta.yanked() => ChangeAction::Unyanked,
+                Some(_) => continue,
+            };
+            changes.push(Change {
+                name: meta.name,
+                version: meta.version,
+                action,
+                timestamp: timestamp.clone(),
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Versions in an index file blob, invalid lines are skipped
+///
+fn read_blob(repo: &Repository, id: Oid) -> Vec<CrateMeta> {
+    if id.is_zero() {
+        return Vec::new();
+    }
+    let blob = match repo.find_blob(id) {
+        Ok(blob) => blob,
+        Err(_) => return Vec::new(),
+    };
+
+    String::from_utf8_lossy(blob.content())
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| CrateMeta::from_line(line).ok())
+        .collect()
+}
+
+/// Watcher of the index `HEAD` of a registry, shared by the clients waiting for its changes
+///
+/// One thread checks `HEAD` every second while there are waiting clients or open streams.
+/// It wakes the waiting clients up when `HEAD` moves, and sends the changes to the
+/// streams, so they do not hold a thread each.
+///
+#[derive(Clone)]
+pub struct ChangeNotifier {
+    index_path: PathBuf,
+    state: Arc<Mutex<NotifierState>>,
+}
+
+#[derive(Default)]
+struct NotifierState {
+    /// The thread checking `HEAD` is running
+    running: bool,
+    waiters: Vec<Waiter>,
+    /// Streams not yet taken by the thread
+    new_streams: Vec<ChangeStream>,
+    /// Open streams, the new ones included
+    streams: usize,
+}
+
+struct Waiter {
+    head: String,
+    deadline: Instant,
+    sender: oneshot::Sender<()>,
+}
+
+struct ChangeStream {
+    since: Option<String>,
+    /// Time of the last events or comment sent
+    sent: Instant,
+    sender: UnboundedSender<String>,
+}
+
+impl ChangeNotifier {
+    pub fn new<P: Into<PathBuf>>(index_path: P) -> Self {
+        ChangeNotifier {
+            index_path: index_path.into(),
+            state: Arc::new(Mutex::new(NotifierState::default())),
+        }
+    }
+
+    /// Returns a receiver completed when `HEAD` of the index is no longer commit `head`,
+    /// or at `deadline`
+    ///
+    pub fn wait(&self, head: String, deadline: Instant) -> SkrdResult<oneshot::Receiver<()>> {
+        let (sender, receiver) = oneshot::channel();
+        let mut state = self.state.lock()?;
+        state.waiters.push(Waiter {
+            head,
+            deadline,
+            sender,
+        });
+        self.start(&mut state);
+
+        Ok(receiver)
+    }
+
+    /// Returns a stream of the Server-Sent Events of the changes after commit `since`, or
+    /// `None` if `MAX_STREAMS` streams are open
+    ///
+    /// Every change is an event named by its action. A `cursor` event follows the changes
+    /// of the commits read, and a `reset` event is sent if `since` is not in the history of
+    /// the index. Their ids are the cursors, so clients resume from them with
+    /// `Last-Event-ID`.
+    ///
+    pub fn stream(&self, since: Option<String>) -> SkrdResult<Option<UnboundedReceiver<String>>> {
+        let mut state = self.state.lock()?;
+        if state.streams >= MAX_STREAMS {
+            return Ok(None);
+        }
+
+        let (sender, receiver) = mpsc::unbounded();
+        state.new_streams.push(ChangeStream {
+            since,
+            sent: Instant::now(),
+            sender,
+        });
+        state.streams += 1;
+        self.start(&mut state);
+
+        Ok(Some(receiver))
+    }
+
+    fn start(&self, state: &mut NotifierState) {
+        if !state.running {
+            state.running = true;
+            let notifier = self.clone();
+            std::thread::spawn(move || notifier.run());
+        }
+    }
+
+    /// Check `HEAD` until no client waits and no stream is open
+    ///
+    fn run(&self) {
+        let repo = Repository::open(&self.index_path);
+        let mut streams: Vec<ChangeStream> = Vec::new();
+        loop {
+            let head = repo
+                .as_ref()
+                .ok()
+                .and_then(|repo| repo.refname_to_id("HEAD").ok())
+                .map(|head| head.to_string());
+            let now = Instant::now();
+
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            streams.append(&mut state.new_streams);
+            let waiters = std::mem::take(&mut state.waiters);
+            for waiter in waiters {
+                if waiter.sender.is_canceled() {
+                    continue;
+                }
+                // clients read the index again, errors included
+                if head.as_ref() != Some(&waiter.head) || now >= waiter.deadline {
+                    let _ = waiter.sender.send(());
+                } else {
+                    state.waiters.push(waiter);
+                }
+            }
+            drop(state);
+
+            // changes are read without the lock, once for the streams at the same cursor
+            match (&repo, &head) {
+                (Ok(repo), Some(head)) => {
+                    let mut pages = BTreeMap::new();
+                    streams = streams
+                        .into_iter()
+                        .filter_map(|mut stream| {
+                            if send_events(repo, head, &mut stream, &mut pages, now) {
+                                Some(stream)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect();
+                }
+                _ => {
+                    error!("Index HEAD of {} read error", self.index_path.display());
+                    streams.clear();
+                }
+            }
+            // streams behind `HEAD` read their next pages without waiting
+            let behind = streams
+                .iter()
+                .any(|stream| stream.since.as_ref() != head.as_ref());
+
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.streams = streams.len() + state.new_streams.len();
+            if state.waiters.is_empty() && state.streams == 0 {
+                state.running = false;
+                return;
+            }
+            drop(state);
+
+            if !behind {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Send the events of the changes after the cursor of `stream` until `head`, or a comment
+/// if it is idle, returns false if the stream is closed or the changes fail to be read
+///
+fn send_events(
+    repo: &Repository,
+    head: &str,
+    stream: &mut ChangeStream,
+    pages: &mut BTreeMap<Option<String>, ChangePage>,
+    now: Instant,
+) -> bool {
+    if stream.sender.is_closed() {
+        return false;
+    }
+
+    let mut events = String::new();
+    if stream.since.as_deref() != Some(head) {
+        let page = match pages.entry(stream.since.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match changes_after(repo, stream.since.as_deref()) {
+                Ok(page) => entry.insert(page),
+                Err(e) => {
+                    error!("Changes of {:?} read error: {}", repo.path(), e);
+                    return false;
+                }
+            },
+        };
+        events = change_events(page);
+        stream.since = Some(page.cursor.clone());
+    }
+
+    // comments keep the connection alive, and find closed streams
+    if events.is_empty() && now >= stream.sent + KEEP_ALIVE {
+        events.push_str(": keep-alive\n\n");
+    }
+    if events.is_empty() {
+        return true;
+    }
+    stream.sent = now;
+    stream.sender.unbounded_send(events).is_ok()
+}
+
+/// Server-Sent Events of a page of changes
+///
+fn change_events(page: &ChangePage) -> String {
+    if page.reset {
+        return format!("event: reset\nid: {0}\ndata: {0}\n\n", page.cursor);
+    }
+
+    let mut events = String::new();
+    for change in &page.changes {
+        let data = serde_json::to_string(change).unwrap_or_default();
+        events.push_str(&format!("event: {}\ndata: {}\n\n", change.action, data));
+    }
+    events.push_str(&format!(
+        "event: cursor\nid: {0}\ndata: {0}\n\n",
+        page.cursor
+    ));
+    events
+}
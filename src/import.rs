@@ -0,0 +1,620 @@
+use crate::bundle::file_sum;
+use crate::error::{SkrdError, SkrdResult};
+use crate::index::{commit_index, index_files_at, read_index_file, version_key};
+use crate::registry::{CrateMeta, Dependency, DependencyKind, Registry, CRATES_IO_INDEX};
+use crate::util::{get_index_path, is_valid_crate_name};
+use digest::Digest;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use git2::Oid;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+const CARGO_CHECKSUM_FILE: &str = ".cargo-checksum.json";
+
+/// `Cargo.toml` in a `.crate` file, which is normalized by `cargo package`
+#[derive(Deserialize, Debug)]
+struct CargoManifest {
+    package: CargoPackage,
+    #[serde(flatten)]
+    dependencies: DependencyTables,
+    #[serde(default)]
+    target: BTreeMap<String, DependencyTables>,
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct CargoPackage {
+    name: String,
+    version: String,
+    links: Option<String>,
+    rust_version: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+struct DependencyTables {
+    #[serde(default)]
+    dependencies: BTreeMap<String, DependencySpec>,
+    #[serde(default, alias = "build_dependencies")]
+    build_dependencies: BTreeMap<String, DependencySpec>,
+    #[serde(default, alias = "dev_dependencies")]
+    dev_dependencies: BTreeMap<String, DependencySpec>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DependencySpec {
+    Version(String),
+    Detailed(DetailedDependency),
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+struct DetailedDependency {
+    version: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    optional: bool,
+    #[serde(alias = "default_features")]
+    default_features: Option<bool>,
+    package: Option<String>,
+    registry: Option<String>,
+    registry_index: Option<String>,
+}
+
+/// Layout of the directory to be imported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// `.crate` files
+    Crates,
+    /// A directory created by `cargo vendor`
+    Vendor,
+    /// A directory created by `cargo local-registry`, with `index` and flat `.crate` files
+    LocalRegistry,
+}
+
+impl ImportFormat {
+    /// Guess the layout of `dir`
+    ///
+    pub fn detect(dir: &Path) -> SkrdResult<Self> {
+        if dir.join(Registry::INDEX_DIRECTORY).is_dir() {
+            return Ok(ImportFormat::LocalRegistry);
+        }
+
+        for entry in std::fs::read_dir(dir)? {
+            if entry?.path().join(CARGO_CHECKSUM_FILE).is_file() {
+                return Ok(ImportFormat::Vendor);
+            }
+        }
+
+        Ok(ImportFormat::Crates)
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = SkrdError;
+
+    fn from_str(s: &str) -> SkrdResult<Self> {
+        match s {
+            "crates" => Ok(ImportFormat::Crates),
+            "vendor" => Ok(ImportFormat::Vendor),
+            "local-registry" => Ok(ImportFormat::LocalRegistry),
+            _ => Err(SkrdError::Custom(format!("unknown import format: {}", s))),
+        }
+    }
+}
+
+impl Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ImportFormat::Crates => write!(f, "crates"),
+            ImportFormat::Vendor => write!(f, "vendor"),
+            ImportFormat::LocalRegistry => write!(f, "local-registry"),
+        }
+    }
+}
+
+/// `.cargo-checksum.json` in each crate directory of `cargo vendor`
+#[derive(Deserialize, Debug)]
+struct CargoChecksum {
+    files: BTreeMap<String, String>,
+    /// Checksum of the `.crate` file, `None` for crates not from a registry
+    package: Option<String>,
+}
+
+/// Result of `import_crates`
+#[derive(Debug, Default)]
+pub struct CrateImport {
+    /// Imported versions
+    pub imported: Vec<String>,
+    /// Versions already in the registry with the same checksum
+    pub duplicates: Vec<String>,
+    /// Versions already in the registry with different checksums or name cases
+    pub conflicts: Vec<String>,
+    /// Files which can not be imported, and the reasons
+    pub failed: Vec<(PathBuf, String)>,
+    /// The index commit, `None` if nothing is imported
+    pub commit: Option<Oid>,
+}
+
+/// Import all `.crate` files under `dir` into `registry` and commit the index
+///
+pub fn import_crates(registry: &Registry, dir: &Path) -> SkrdResult<CrateImport> {
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension() == Some("crate".as_ref()) {
+            files.push(entry.into_path());
+        }
+    }
+
+    let mut report = CrateImport::default();
+    let mut crates = Vec::with_capacity(files.len());
+    let results: Vec<(PathBuf, SkrdResult<CrateMeta>)> = files
+        .into_par_iter()
+        .map(|path| {
+            let meta = read_crate_file(registry, &path);
+            (path, meta)
+        })
+        .collect();
+    for (path, result) in results {
+        match result {
+            Ok(meta) => crates.push((path, meta)),
+            Err(e) => report.failed.push((path, e.to_string())),
+        }
+    }
+
+    let message = format!("Import crates from {}", dir.display());
+    add_crates(registry, crates, &message, &mut report)?;
+
+    Ok(report)
+}
+
+/// Import the crates vendored by `cargo vendor` in `dir` into `registry`
+///
+/// Every crate is checked against its `.cargo-checksum.json` and packed again, so the
+/// checksums in the index are of the new `.crate` files.
+///
+pub fn import_vendor(registry: &Registry, dir: &Path) -> SkrdResult<CrateImport> {
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.join(CARGO_CHECKSUM_FILE).is_file() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+
+    let mut staging = registry.root().as_os_str().to_owned();
+    staging.push(".import");
+    let staging = PathBuf::from(staging);
+    if staging.exists() {
+        return Err(SkrdError::Custom(format!(
+            "{} already exists, remove it if no other import is running",
+            staging.display()
+        )));
+    }
+    create_dir_all(&staging)?;
+
+    let mut report = CrateImport::default();
+    let mut crates = Vec::with_capacity(dirs.len());
+    let results: Vec<(PathBuf, SkrdResult<(PathBuf, CrateMeta)>)> = dirs
+        .into_par_iter()
+        .map(|path| {
+            let result = pack_vendored_crate(registry, &path, &staging);
+            (path, result)
+        })
+        .collect();
+    for (path, result) in results {
+        match result {
+            Ok(packed) => crates.push(packed),
+            Err(e) => report.failed.push((path, e.to_string())),
+        }
+    }
+
+    let message = format!("Import vendored crates from {}", dir.display());
+    let result = add_crates(registry, crates, &message, &mut report);
+
+    std::fs::remove_dir_all(&staging)?;
+    result?;
+
+    Ok(report)
+}
+
+/// Verify a vendored crate and pack it into `staging`
+///
+fn pack_vendored_crate(
+    registry: &Registry,
+    dir: &Path,
+    staging: &Path,
+) -> SkrdResult<(PathBuf, CrateMeta)> {
+    let checksums: CargoChecksum =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(CARGO_CHECKSUM_FILE))?)?;
+    if checksums.package.is_none() {
+        return Err(SkrdError::StaticCustom("the crate is not from a registry"));
+    }
+
+    for (file, expected) in &checksums.files {
+        let actual = hex::encode(file_sum(&dir.join(file))?);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(SkrdError::Custom(format!("{} is modified", file)));
+        }
+    }
+
+    let manifest = std::fs::read_to_string(dir.join("Cargo.toml"))?;
+    // vendored crates depend on each other
+    let mut meta = crate_meta_from_manifest(registry, &manifest, [0u8; 32], None)?;
+
+    let root = format!("{}-{}", meta.name, meta.version);
+    let path = staging.join(format!("{}.crate", root));
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(File::create(&path)?, Compression::default()));
+    for file in checksums.files.keys() {
+        let source = dir.join(file);
+        let metadata = source.metadata()?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+        builder.append_data(
+            &mut header,
+            format!("{}/{}", root, file),
+            File::open(&source)?,
+        )?;
+    }
+    builder.into_inner()?.finish()?;
+
+    meta.checksum = file_sum(&path)?;
+
+    Ok((path, meta))
+}
+
+/// Import a `cargo local-registry` directory into `registry`
+///
+/// Index entries are reused as they are, crate files are checked against them.
+///
+pub fn import_local_registry(registry: &Registry, dir: &Path) -> SkrdResult<CrateImport> {
+    let mut report = CrateImport::default();
+    let mut crates = Vec::new();
+
+    for index_file in index_files_at(&dir.join(Registry::INDEX_DIRECTORY)) {
+        let versions = match read_index_file(&index_file) {
+            Ok(versions) => versions,
+            Err(e) => {
+                report.failed.push((index_file, e.to_string()));
+                continue;
+            }
+        };
+
+        for meta in versions {
+            let path = dir.join(format!("{}-{}.crate", meta.name, meta.version));
+            if !path.is_file() {
+                report
+                    .failed
+                    .push((path, format!("crate file of {} is missing", meta)));
+                continue;
+            }
+            if file_sum(&path)? != meta.checksum {
+                report
+                    .failed
+                    .push((path, format!("checksum of {} does not match", meta)));
+                continue;
+            }
+            crates.push((path, meta));
+        }
+    }
+
+    let message = format!("Import local registry {}", dir.display());
+    add_crates(registry, crates, &message, &mut report)?;
+
+    Ok(report)
+}
+
+/// Add crate files with their index entries into `registry`, and commit the index
+///
+/// Versions already in the index are never overwritten, they are reported as duplicates
+/// or conflicts.
+///
+pub fn add_crates(
+    registry: &Registry,
+    crates: Vec<(PathBuf, CrateMeta)>,
+    message: &str,
+    report: &mut CrateImport,
+) -> SkrdResult<()> {
+    // index files and their versions, including the new ones
+    let mut index: BTreeMap<PathBuf, Vec<CrateMeta>> = BTreeMap::new();
+    let mut added: BTreeMap<PathBuf, Vec<CrateMeta>> = BTreeMap::new();
+
+    for (path, meta) in crates {
+        if !is_valid_crate_name(&meta.name) {
+            report
+                .failed
+                .push((path, format!("invalid crate name '{}'", meta.name)));
+            continue;
+        }
+
+        let index_file = registry.index_path().join(get_index_path(&meta.name));
+        if !index.contains_key(&index_file) {
+            let versions = if index_file.exists() {
+                read_index_file(&index_file)?
+            } else {
+                Vec::new()
+            };
+            index.insert(index_file.clone(), versions);
+        }
+        let versions = &index[&index_file];
+
+        if let Some(other) = versions.iter().find(|v| v.name != meta.name) {
+            warn!(
+                "{} conflicts with the existing crate name '{}'.",
+                meta, other.name
+            );
+            report.conflicts.push(meta.to_string());
+            continue;
+        }
+
+        let crate_path = registry.crate_path(&meta);
+
+        if let Some(existing) = versions
+            .iter()
+            .find(|v| version_key(&v.version) == version_key(&meta.version))
+        {
+            if existing.checksum == meta.checksum {
+                if !registry.storage().exists(&crate_path)? {
+                    store_crate_file(registry, &path, &crate_path)?;
+                }
+                report.duplicates.push(meta.to_string());
+            } else {
+                warn!(
+                    "{} conflicts with the existing version {} of a different checksum.",
+                    path.display(),
+                    existing
+                );
+                report.conflicts.push(meta.to_string());
+            }
+            continue;
+        }
+
+        let stored = registry.storage().read(&crate_path)?;
+        if stored.is_some_and(|content| sha2::Sha256::digest(&content)[..] != meta.checksum) {
+            warn!(
+                "{} conflicts with the existing crate file {}.",
+                path.display(),
+                crate_path
+            );
+            report.conflicts.push(meta.to_string());
+            continue;
+        }
+
+        store_crate_file(registry, &path, &crate_path)?;
+        info!("Crate {} is imported.", meta);
+        report.imported.push(meta.to_string());
+
+        if let Some(versions) = index.get_mut(&index_file) {
+            versions.push(meta.clone());
+        }
+        added.entry(index_file).or_default().push(meta);
+    }
+
+    if added.is_empty() {
+        return Ok(());
+    }
+
+    for (index_file, versions) in added {
+        if let Some(parent) = index_file.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_file)?;
+        for meta in versions {
+            writeln!(file, "{}", serde_json::to_string(&meta)?)?;
+        }
+    }
+
+    report.commit = Some(commit_index(registry, message)?);
+
+    Ok(())
+}
+
+fn store_crate_file(registry: &Registry, from: &Path, crate_path: &str) -> SkrdResult<()> {
+    let storage = registry.storage();
+    let local_path = storage.local_path().map(|root| root.join(crate_path));
+    if local_path.as_deref() == Some(from) {
+        return Ok(());
+    }
+    storage.write(crate_path, &std::fs::read(from)?)
+}
+
+/// Read the index entry of a `.crate` file from the `Cargo.toml` in it
+///
+pub fn read_crate_file(registry: &Registry, path: &Path) -> SkrdResult<CrateMeta> {
+    let mut content = Vec::new();
+    File::open(path)?.read_to_end(&mut content)?;
+
+    let mut manifest = None;
+    let mut archive = tar::Archive::new(GzDecoder::new(content.as_slice()));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let mut components = entry_path.components();
+        match (components.next(), components.next(), components.next()) {
+            (Some(Component::Normal(root)), Some(Component::Normal(file)), None)
+                if file == "Cargo.toml" =>
+            {
+                let mut toml = String::new();
+                entry.read_to_string(&mut toml)?;
+                manifest = Some((root.to_string_lossy().into_owned(), toml));
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let (root, manifest) =
+        manifest.ok_or_else(|| SkrdError::StaticCustom("Cargo.toml is not found in the crate"))?;
+
+    let mut checksum = [0u8; 32];
+    checksum.copy_from_slice(&sha2::Sha256::digest(&content));
+
+    // dependencies without a registry are from crates.io
+    let default_registry = if registry.mirror_config().is_some() {
+        None
+    } else {
+        Some(CRATES_IO_INDEX)
+    };
+    let meta = crate_meta_from_manifest(registry, &manifest, checksum, default_registry)?;
+    if root != format!("{}-{}", meta.name, meta.version) {
+        return Err(SkrdError::Custom(format!(
+            "the root directory {} does not match {}",
+            root, meta
+        )));
+    }
+
+    Ok(meta)
+}
+
+/// Generate the index entry of a crate from its normalized `Cargo.toml`
+///
+/// `default_registry` is the index url of dependencies without `registry-index`,
+/// `None` means they are in `registry`.
+///
+pub fn crate_meta_from_manifest(
+    registry: &Registry,
+    manifest: &str,
+    checksum: [u8; 32],
+    default_registry: Option<&str>,
+) -> SkrdResult<CrateMeta> {
+    let manifest: CargoManifest = toml::from_str(manifest)?;
+
+    let mut deps = Vec::new();
+    let tables = manifest
+        .target
+        .iter()
+        .map(|(target, tables)| (Some(target.as_str()), tables));
+    for (target, tables) in std::iter::once((None, &manifest.dependencies)).chain(tables) {
+        add_dependencies(registry, &mut deps, tables, target, default_registry)?;
+    }
+    deps.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // features using `dep:` or `?` syntax are kept apart for older cargo
+    let (features2, features): (BTreeMap<_, _>, BTreeMap<_, _>) =
+        manifest.features.into_iter().partition(|(_, values)| {
+            values
+                .iter()
+                .any(|value| value.starts_with("dep:") || value.contains("?/"))
+        });
+    let (features2, v) = if features2.is_empty() {
+        (None, None)
+    } else {
+        (Some(features2), Some(2))
+    };
+
+    Ok(CrateMeta {
+        name: manifest.package.name,
+        version: manifest.package.version,
+        deps: Some(deps),
+        checksum,
+        features: Some(features),
+        features2,
+        yanked: Some(false),
+        links: manifest.package.links.map(Some),
+        rust_version: manifest.package.rust_version,
+        pubtime: Some(chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+        v,
+        extra: serde_json::Map::new(),
+    })
+}
+
+fn add_dependencies(
+    registry: &Registry,
+    deps: &mut Vec<Dependency>,
+    tables: &DependencyTables,
+    target: Option<&str>,
+    default_registry: Option<&str>,
+) -> SkrdResult<()> {
+    let kinds = [
+        (&tables.dependencies, DependencyKind::Normal),
+        (&tables.build_dependencies, DependencyKind::Build),
+        (&tables.dev_dependencies, DependencyKind::Dev),
+    ];
+
+    for (table, kind) in kinds.iter() {
+        for (name, spec) in table.iter() {
+            let detail = match spec {
+                DependencySpec::Version(req) => DetailedDependency {
+                    version: Some(req.clone()),
+                    ..DetailedDependency::default()
+                },
+                DependencySpec::Detailed(detail) => detail.clone(),
+            };
+
+            // `cargo package` strips path-only dev-dependencies, older versions kept them
+            let req = match detail.version {
+                Some(req) => normalize_req(&req),
+                None if *kind == DependencyKind::Dev => continue,
+                None => {
+                    return Err(SkrdError::Custom(format!(
+                        "dependency '{}' has no version requirement",
+                        name
+                    )))
+                }
+            };
+
+            if let Some(registry_name) = &detail.registry {
+                return Err(SkrdError::Custom(format!(
+                    "dependency '{}' refers to registry '{}' by name, which can not be resolved",
+                    name, registry_name
+                )));
+            }
+
+            let dep_registry = match detail.registry_index {
+                Some(url) if url.trim_end_matches('/') == registry.index_url() => None,
+                Some(url) => Some(url),
+                None => default_registry.map(str::to_owned),
+            };
+
+            deps.push(Dependency {
+                name: name.clone(),
+                req,
+                features: Some(detail.features),
+                optional: Some(detail.optional),
+                default_features: Some(detail.default_features.unwrap_or(true)),
+                target: Some(target.map(str::to_owned)),
+                kind: Some(*kind),
+                registry: dep_registry,
+                package: detail.package,
+                extra: serde_json::Map::new(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Write version requirements the way cargo sends them, e.g. `1.0` as `^1.0`
+///
+fn normalize_req(req: &str) -> String {
+    req.split(',')
+        .map(|part| {
+            let part = part.trim();
+            if part.starts_with(|c: char| c.is_ascii_digit()) {
+                format!("^{}", part)
+            } else {
+                part.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
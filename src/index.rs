@@ -0,0 +1,951 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::import::read_crate_file;
+use crate::registry::{CrateMeta, DependencyKind, Registry, UrlConfig};
+use crate::storage::{require_local, Layout};
+use crate::util::{
+    get_index_path, is_index_path, is_valid_crate_name, legacy_crate_path, normalize_crate_name,
+};
+use digest::Digest;
+use git2::{IndexAddOption, Oid};
+use rayon::prelude::*;
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Get paths of all index files in the index, see `index_files_at`
+///
+pub fn index_files(registry: &Registry) -> impl Iterator<Item = PathBuf> {
+    index_files_at(registry.index_path())
+}
+
+/// Get paths of all index files in an index directory
+///
+/// Only files at paths of the shape of index files are returned, see `is_index_path`, so
+/// `config.json`, dotfiles and other files kept in the repository are skipped.
+///
+pub fn index_files_at(index_path: &Path) -> impl Iterator<Item = PathBuf> {
+    let root = index_path.to_owned();
+
+    walkdir::WalkDir::new(index_path)
+        .max_depth(3)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(move |result| match result {
+            Ok(entry) => {
+                if !entry.file_type().is_file() {
+                    return None;
+                }
+                let relative = entry.path().strip_prefix(&root).ok()?;
+                if !is_index_path(relative) {
+                    return None;
+                }
+                Some(entry.into_path())
+            }
+            Err(e) => {
+                warn!("Walk error: {}", e);
+                None
+            }
+        })
+}
+
+/// Read all versions of a crate from an index file
+///
+pub fn read_index_file<P: AsRef<Path>>(path: P) -> SkrdResult<Vec<CrateMeta>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut versions = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        versions.push(CrateMeta::from_line(&line)?);
+    }
+
+    Ok(versions)
+}
+
+/// A crate version depending on another crate
+#[derive(Serialize, Debug, Clone)]
+pub struct ReverseDependency {
+    #[serde(rename = "crate")]
+    pub name: String,
+    pub version: String,
+    pub req: String,
+    pub kind: DependencyKind,
+    pub optional: bool,
+    pub target: Option<String>,
+    pub yanked: bool,
+}
+
+/// Crates depending on each crate in the index at a commit
+///
+/// Only the index files of the dependents are kept, they are read again for the
+/// requirements of their versions, so a mirror of crates.io fits in memory.
+///
+#[derive(Debug, Default)]
+pub struct ReverseIndex {
+    /// `HEAD` of the index which is read
+    pub commit: Option<Oid>,
+    files: Vec<PathBuf>,
+    /// Positions in `files` of the dependents of a crate, by its normalized name
+    dependents: HashMap<String, Vec<u32>>,
+    /// Normalized names of the crates in the index
+    crates: HashSet<String>,
+}
+
+impl ReverseIndex {
+    /// Read all index files of `registry`, `commit` is the `HEAD` of its index
+    ///
+    pub fn read(registry: &Registry, commit: Option<Oid>) -> SkrdResult<Self> {
+        let mut results: Vec<(usize, PathBuf, SkrdResult<Vec<CrateMeta>>)> = index_files(registry)
+            .enumerate()
+            .par_bridge()
+            .map(|(i, path)| {
+                let versions = read_index_file(&path);
+                (i, path, versions)
+            })
+            .collect();
+        results.sort_by_key(|(i, _, _)| *i);
+
+        let mut index = ReverseIndex {
+            commit,
+            ..ReverseIndex::default()
+        };
+        for (_, path, versions) in results {
+            let versions = versions.map_err(|e| {
+                SkrdError::Custom(format!("index file {} error: {}", path.display(), e))
+            })?;
+            let position = index.files.len() as u32;
+            let mut depended = HashSet::new();
+            for meta in &versions {
+                index.crates.insert(normalize_crate_name(&meta.name));
+                for dep in meta.deps().iter().filter(|dep| dep.registry.is_none()) {
+                    depended.insert(normalize_crate_name(dep.crate_name()));
+                }
+            }
+            for name in depended {
+                index.dependents.entry(name).or_default().push(position);
+            }
+            index.files.push(path);
+        }
+
+        Ok(index)
+    }
+
+    /// Find all crate versions which depend on crate `name`, returns `None` if the crate
+    /// is not in the index
+    ///
+    /// Results are in the order of index files and versions in them.
+    ///
+    pub fn dependents(&self, name: &str) -> SkrdResult<Option<Vec<ReverseDependency>>> {
+        let name = normalize_crate_name(name);
+        if !self.crates.contains(&name) {
+            return Ok(None);
+        }
+
+        let mut dependents = Vec::new();
+        for position in self.dependents.get(&name).into_iter().flatten() {
+            for meta in read_index_file(&self.files[*position as usize])? {
+                for dep in meta.deps_on(&name) {
+                    dependents.push(ReverseDependency {
+                        name: meta.name.clone(),
+                        version: meta.version.clone(),
+                        req: dep.req.clone(),
+                        kind: dep.kind(),
+                        optional: dep.optional(),
+                        target: dep.target().map(str::to_owned),
+                        yanked: meta.yanked(),
+                    });
+                }
+            }
+        }
+
+        Ok(Some(dependents))
+    }
+}
+
+/// `ReverseIndex` of the latest index commit, shared by the workers of a server
+///
+/// It is read again when the `HEAD` of the index moves.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ReverseIndexCache(Arc<Mutex<Option<Arc<ReverseIndex>>>>);
+
+impl ReverseIndexCache {
+    pub fn get(&self, registry: &Registry) -> SkrdResult<Arc<ReverseIndex>> {
+        let head = git2::Repository::open(registry.index_path())?
+            .refname_to_id("HEAD")
+            .ok();
+
+        // other requests wait for the index being read instead of reading it again
+        let mut cached = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(index) = cached.as_ref() {
+            if head.is_some() && index.commit == head {
+                return Ok(index.clone());
+            }
+        }
+
+        let index = Arc::new(ReverseIndex::read(registry, head)?);
+        *cached = Some(index.clone());
+        Ok(index)
+    }
+}
+
+/// Commit all changes in the index directory to `HEAD`
+///
+pub fn commit_index(registry: &Registry, message: &str) -> SkrdResult<Oid> {
+    let repo = git2::Repository::open(registry.index_path())?;
+
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"].iter(), None)?;
+    index.write()?;
+
+    let tree = index.write_tree().and_then(|id| repo.find_tree(id))?;
+    let sig = repo.signature()?;
+
+    let parent = repo
+        .head()
+        .ok()
+        .and_then(|reference| reference.target())
+        .and_then(|target| repo.find_commit(target).ok());
+
+    match parent {
+        Some(parent) => Ok(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])?),
+        None => Ok(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[])?),
+    }
+}
+
+/// A problem found by `check_index`
+#[derive(Debug, Clone)]
+pub enum IndexIssue {
+    /// The index file is not at the path of its crate name
+    Misplaced { path: PathBuf, expected: String },
+    /// A line can not be parsed
+    InvalidLine {
+        path: PathBuf,
+        line: usize,
+        error: String,
+    },
+    /// The crate name of a line does not match its file
+    NameMismatch {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+    /// A version is listed more than once
+    DuplicateVersion {
+        path: PathBuf,
+        line: usize,
+        version: String,
+    },
+    /// `config.json` does not match the registry configuration
+    ConfigJson { expected: UrlConfig },
+}
+
+impl IndexIssue {
+    /// Get the index file of this issue, `None` for `config.json`
+    ///
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            IndexIssue::Misplaced { path, .. }
+            | IndexIssue::InvalidLine { path, .. }
+            | IndexIssue::NameMismatch { path, .. }
+            | IndexIssue::DuplicateVersion { path, .. } => Some(path),
+            IndexIssue::ConfigJson { .. } => None,
+        }
+    }
+}
+
+impl Display for IndexIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            IndexIssue::Misplaced { path, expected } => {
+                write!(f, "{} should be at {}", path.display(), expected)
+            }
+            IndexIssue::InvalidLine { path, line, error } => {
+                write!(
+                    f,
+                    "{}:{} can not be parsed: {}",
+                    path.display(),
+                    line,
+                    error
+                )
+            }
+            IndexIssue::NameMismatch { path, line, name } => {
+                write!(f, "{}:{} belongs to crate '{}'", path.display(), line, name)
+            }
+            IndexIssue::DuplicateVersion {
+                path,
+                line,
+                version,
+            } => write!(
+                f,
+                "{}:{} version {} is duplicated",
+                path.display(),
+                line,
+                version
+            ),
+            IndexIssue::ConfigJson { expected } => write!(
+                f,
+                "{} does not match the registry (dl: {}, api: {})",
+                Registry::CONFIG_JSON_FILE,
+                expected.dl,
+                expected.api.as_deref().unwrap_or("none")
+            ),
+        }
+    }
+}
+
+/// Result of `check_index`
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    pub files: usize,
+    pub versions: usize,
+    pub issues: Vec<IndexIssue>,
+}
+
+/// Check that every index file is at the right path and all lines in it are valid
+///
+pub fn check_index(registry: &Registry) -> SkrdResult<IndexReport> {
+    let mut results: Vec<(usize, usize, Vec<IndexIssue>)> = index_files(registry)
+        .enumerate()
+        .par_bridge()
+        .map(|(i, path)| match check_index_file(registry, &path) {
+            Ok((versions, issues)) => (i, versions, issues),
+            Err(e) => (
+                i,
+                0,
+                vec![IndexIssue::InvalidLine {
+                    path,
+                    line: 0,
+                    error: e.to_string(),
+                }],
+            ),
+        })
+        .collect();
+    results.sort_by_key(|(i, _, _)| *i);
+
+    let mut report = IndexReport {
+        files: results.len(),
+        ..IndexReport::default()
+    };
+    for (_, versions, issues) in results {
+        report.versions += versions;
+        report.issues.extend(issues);
+    }
+
+    let expected = UrlConfig::from(registry);
+    let found = std::fs::read_to_string(registry.index_path().join(Registry::CONFIG_JSON_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str::<UrlConfig>(&content).ok());
+    if found.as_ref() != Some(&expected) {
+        report.issues.push(IndexIssue::ConfigJson { expected });
+    }
+
+    Ok(report)
+}
+
+fn check_index_file(registry: &Registry, path: &Path) -> SkrdResult<(usize, Vec<IndexIssue>)> {
+    let mut issues = Vec::new();
+
+    // index files are named after valid crate names, see `index_files`
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let expected = get_index_path(file_name);
+    if relative_index_path(registry, path).as_ref() != Some(&expected) {
+        issues.push(IndexIssue::Misplaced {
+            path: path.to_owned(),
+            expected,
+        });
+    }
+
+    let mut versions = BTreeSet::new();
+    let reader = BufReader::new(File::open(path)?);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        // blank lines are skipped by cargo and `read_index_file`
+        if line.trim().is_empty() {
+            continue;
+        }
+        let meta = match CrateMeta::from_line(&line) {
+            Ok(meta) => meta,
+            Err(e) => {
+                issues.push(IndexIssue::InvalidLine {
+                    path: path.to_owned(),
+                    line: i + 1,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        if !meta.name.eq_ignore_ascii_case(file_name) {
+            issues.push(IndexIssue::NameMismatch {
+                path: path.to_owned(),
+                line: i + 1,
+                name: meta.name.clone(),
+            });
+            continue;
+        }
+
+        if !versions.insert(version_key(&meta.version).to_owned()) {
+            issues.push(IndexIssue::DuplicateVersion {
+                path: path.to_owned(),
+                line: i + 1,
+                version: meta.version.clone(),
+            });
+        }
+    }
+
+    Ok((versions.len(), issues))
+}
+
+/// Fix the issues found by `check_index` and commit the changes
+///
+/// Lines are moved to the files of their crate names, invalid lines and duplicate
+/// versions are dropped, and `config.json` is rewritten. Valid lines are kept as is.
+/// All changes are in one commit.
+///
+pub fn fix_index(registry: &Registry, report: &IndexReport) -> SkrdResult<Option<Oid>> {
+    let mut fixed = false;
+    if report
+        .issues
+        .iter()
+        .any(|issue| matches!(issue, IndexIssue::ConfigJson { .. }))
+    {
+        std::fs::write(
+            registry.index_path().join(Registry::CONFIG_JSON_FILE),
+            serde_json::to_string_pretty(&UrlConfig::from(registry))?,
+        )?;
+        fixed = true;
+    }
+
+    let files: BTreeSet<&Path> = report.issues.iter().filter_map(IndexIssue::path).collect();
+    for path in files {
+        fix_index_file(registry, path)?;
+        fixed = true;
+    }
+
+    if !fixed {
+        return Ok(None);
+    }
+    Ok(Some(commit_index(registry, "Fix index consistency")?))
+}
+
+fn fix_index_file(registry: &Registry, path: &Path) -> SkrdResult<()> {
+    // the file may have been merged into another one
+    if !path.exists() {
+        return Ok(());
+    }
+    // only index files are rewritten or removed
+    match path.strip_prefix(registry.index_path()) {
+        Ok(relative) if is_index_path(relative) => {}
+        _ => {
+            return Err(SkrdError::Custom(format!(
+                "{} is not an index file",
+                path.display()
+            )))
+        }
+    }
+
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+
+    let mut targets: BTreeMap<PathBuf, Vec<(String, &str)>> = BTreeMap::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match CrateMeta::from_line(line) {
+            Ok(meta) if is_valid_crate_name(&meta.name) => {
+                let target = registry.index_path().join(get_index_path(&meta.name));
+                targets
+                    .entry(target)
+                    .or_default()
+                    .push((version_key(&meta.version).to_owned(), line));
+            }
+            Ok(meta) => warn!("Line of invalid crate name '{}' is dropped.", meta.name),
+            Err(e) => warn!("Line is dropped: {}", e),
+        }
+    }
+
+    std::fs::remove_file(path)?;
+
+    for (target, lines) in targets {
+        let mut versions = BTreeSet::new();
+        let mut kept = Vec::new();
+        if target.exists() {
+            let reader = BufReader::new(File::open(&target)?);
+            for line in reader.lines() {
+                if let Ok(meta) = CrateMeta::from_line(&line?) {
+                    versions.insert(version_key(&meta.version).to_owned());
+                }
+            }
+        }
+
+        for (version, line) in lines {
+            if versions.insert(version) {
+                kept.push(line);
+            }
+        }
+
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&target)?;
+        for line in kept {
+            writeln!(file, "{}", line)?;
+        }
+        info!("Index file {} is fixed.", target.display());
+    }
+
+    Ok(())
+}
+
+/// Result of `verify_crates`
+#[derive(Debug, Default)]
+pub struct CrateReport {
+    pub checked: usize,
+    /// Versions in the index without crate files
+    pub missing: Vec<String>,
+    /// Crate files (paths in the storage) of which the checksum does not match the index
+    pub corrupted: Vec<PathBuf>,
+}
+
+/// Verify the crate files of all versions in the index against their checksums
+///
+pub fn verify_crates(registry: &Registry) -> CrateReport {
+    let mut results: Vec<(usize, CrateReport)> = index_files(registry)
+        .enumerate()
+        .par_bridge()
+        .map(|(i, path)| {
+            let mut report = CrateReport::default();
+            let versions = match read_index_file(&path) {
+                Ok(versions) => versions,
+                Err(e) => {
+                    warn!("Index file {} parse error: {}", path.display(), e);
+                    return (i, report);
+                }
+            };
+
+            for meta in versions {
+                let crate_path = registry.crate_path(&meta);
+                report.checked += 1;
+
+                match registry.storage().read(&crate_path) {
+                    Ok(Some(content)) if Sha256::digest(&content)[..] == meta.checksum => {}
+                    Ok(Some(_)) => report.corrupted.push(PathBuf::from(crate_path)),
+                    Ok(None) => report.missing.push(meta.to_string()),
+                    Err(e) => {
+                        warn!("Crate file {} read error: {}", crate_path, e);
+                        report.corrupted.push(PathBuf::from(crate_path));
+                    }
+                }
+            }
+
+            (i, report)
+        })
+        .collect();
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut report = CrateReport::default();
+    for (_, result) in results {
+        report.checked += result.checked;
+        report.missing.extend(result.missing);
+        report.corrupted.extend(result.corrupted);
+    }
+
+    report
+}
+
+/// Result of `collect_garbage`
+#[derive(Debug, Default)]
+pub struct GarbageReport {
+    /// Crate files in the `crates` directory which belong to no version in the index
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+    /// Files which are not crate files or belong to a version in the index, they are kept
+    pub kept: Vec<PathBuf>,
+}
+
+/// Find the crate files in the `crates` directory which belong to no version in the index,
+/// and remove them if `force` is true
+///
+/// Only files named `{crate}-{version}.crate`, or `{sha256-checksum}.crate` in the checksum
+/// layout, are collected. Other files, and crate files of indexed versions at other paths,
+/// are kept.
+///
+pub fn collect_garbage(registry: &Registry, force: bool) -> SkrdResult<GarbageReport> {
+    let crates_path = require_local(registry.storage(), "gc")?;
+    let mut referred = BTreeSet::new();
+    let mut versions = HashSet::new();
+    let mut checksums = HashSet::new();
+    for path in index_files(registry) {
+        for meta in read_index_file(&path)? {
+            referred.insert(crates_path.join(registry.crate_path(&meta)));
+            versions.insert((normalize_crate_name(&meta.name), meta.version));
+            checksums.insert(hex::encode(meta.checksum));
+        }
+    }
+
+    let mut report = GarbageReport::default();
+    if !crates_path.is_dir() {
+        return Ok(report);
+    }
+
+    // children come before their parents, so emptied directories can be removed
+    for entry in walkdir::WalkDir::new(crates_path)
+        .min_depth(1)
+        .contents_first(true)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+    {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            if force && std::fs::read_dir(entry.path())?.next().is_none() {
+                std::fs::remove_dir(entry.path())?;
+            }
+            continue;
+        }
+
+        if referred.contains(entry.path()) {
+            continue;
+        }
+
+        let stem = match entry.file_name().to_str() {
+            Some(name) if name.ends_with(".crate") => &name[..name.len() - ".crate".len()],
+            _ => {
+                report.kept.push(entry.into_path());
+                continue;
+            }
+        };
+        let garbage = if registry.layout() == Layout::Checksum && is_sha256_hex(stem) {
+            !checksums.contains(stem)
+        } else {
+            let candidates = crate_file_versions(stem);
+            !candidates.is_empty()
+                && candidates.iter().all(|(name, version)| {
+                    !versions.contains(&(normalize_crate_name(name), (*version).to_owned()))
+                })
+        };
+        if !garbage {
+            report.kept.push(entry.into_path());
+            continue;
+        }
+
+        report.bytes += entry.metadata()?.len();
+        if force {
+            std::fs::remove_file(entry.path())?;
+        }
+        report.files.push(entry.into_path());
+    }
+
+    Ok(report)
+}
+
+/// Split a crate file name without `.crate` into possible `(name, version)`, a name
+/// may contain `-`, so every split with a valid name and version is returned
+///
+fn crate_file_versions(stem: &str) -> Vec<(&str, &str)> {
+    stem.match_indices('-')
+        .map(|(i, _)| (&stem[..i], &stem[i + 1..]))
+        .filter(|(name, version)| is_valid_crate_name(name) && is_version(version))
+        .collect()
+}
+
+/// Check the shape of a semantic version: `{major}.{minor}.{patch}`, optionally followed
+/// by a pre-release and build metadata
+///
+fn is_version(version: &str) -> bool {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+')
+}
+
+fn is_sha256_hex(s: &str) -> bool {
+    s.len() == 64
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Action of a change of a version
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Published,
+    Yanked,
+    Unyanked,
+}
+
+impl Display for ChangeAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ChangeAction::Published => write!(f, "published"),
+            ChangeAction::Yanked => write!(f, "yanked"),
+            ChangeAction::Unyanked => write!(f, "unyanked"),
+        }
+    }
+}
+
+/// A change of a version, which is a line of an audit log
+///
+/// .json example:
+///
+/// ```json
+/// {"crate":"serde","version":"1.0.0","action":"yanked","timestamp":"2019-07-01T02:00:00Z"}
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    #[serde(rename = "crate")]
+    pub name: String,
+    pub version: String,
+    pub action: ChangeAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+/// Read the changes in an audit log, one JSON object per line, in the order they happened
+///
+pub fn read_audit_log<P: AsRef<Path>>(path: P) -> SkrdResult<Vec<Change>> {
+    let reader = BufReader::new(File::open(path.as_ref())?);
+
+    let mut changes = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let change = serde_json::from_str(&line).map_err(|e| {
+            SkrdError::Custom(format!(
+                "line {} of {} parse error: {}",
+                number + 1,
+                path.as_ref().display(),
+                e
+            ))
+        })?;
+        changes.push(change);
+    }
+
+    Ok(changes)
+}
+
+/// Result of `rebuild_index`
+#[derive(Debug, Default)]
+pub struct RebuildReport {
+    pub versions: usize,
+    pub yanked: usize,
+    /// Crate files which can not be read, with the errors
+    pub failed: Vec<(PathBuf, String)>,
+    /// Crate files of which the path is not the one of their versions in the layout
+    pub misplaced: Vec<PathBuf>,
+    /// Versions conflicting with a version already added, by the casing of the name or
+    /// the build metadata
+    pub conflicts: Vec<PathBuf>,
+    /// Versions in the audit log without crate files
+    pub unknown: Vec<String>,
+    /// The old index directory, which is moved aside
+    pub old_index: Option<PathBuf>,
+    pub commit: Option<Oid>,
+}
+
+/// Rebuild the index from the `Cargo.toml` in the crate files, with a new git history
+///
+/// Yank states are replayed from the `changes` of an audit log. The old index directory
+/// is kept as `index.old-{time}`, and `config.json` is written from the registry config.
+///
+pub fn rebuild_index(registry: &Registry, changes: &[Change]) -> SkrdResult<RebuildReport> {
+    let crates_path = require_local(registry.storage(), "index rebuild")?;
+    let mut report = RebuildReport::default();
+
+    let files: Vec<PathBuf> = walkdir::WalkDir::new(crates_path)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Walk error: {}", e);
+                None
+            }
+        })
+        .filter(|entry| {
+            entry.file_type().is_file() && entry.path().extension() == Some("crate".as_ref())
+        })
+        .map(|entry| entry.into_path())
+        .collect();
+
+    let results: Vec<(PathBuf, SkrdResult<CrateMeta>)> = files
+        .into_par_iter()
+        .map(|path| {
+            let meta = read_crate_file(registry, &path).map(|mut meta| {
+                // the time of reading is not the one of publishing, see the audit log below
+                meta.pubtime = None;
+                meta
+            });
+            (path, meta)
+        })
+        .collect();
+
+    // versions of the index files, by the normalized crate names
+    let mut crates: BTreeMap<String, Vec<CrateMeta>> = BTreeMap::new();
+    for (path, meta) in results {
+        let meta = match meta {
+            Ok(meta) if is_valid_crate_name(&meta.name) => meta,
+            Ok(meta) => {
+                warn!(
+                    "{} has an invalid crate name '{}'.",
+                    path.display(),
+                    meta.name
+                );
+                report
+                    .failed
+                    .push((path, format!("invalid crate name '{}'", meta.name)));
+                continue;
+            }
+            Err(e) => {
+                warn!("{} read error: {}", path.display(), e);
+                report.failed.push((path, e.to_string()));
+                continue;
+            }
+        };
+
+        let expected = crates_path.join(registry.crate_path(&meta));
+        if expected != path {
+            // crate files stored by earlier versions are moved to the lowercased prefix
+            let legacy = registry.layout() == Layout::Prefix
+                && legacy_crate_path(&meta.name, &meta.version).map(|p| crates_path.join(p))
+                    == Some(path.clone());
+            if !legacy || expected.exists() {
+                warn!(
+                    "{} is not the crate file of {} in the layout.",
+                    path.display(),
+                    meta
+                );
+                report.misplaced.push(path);
+                continue;
+            }
+            if let Some(parent) = expected.parent() {
+                create_dir_all(parent)?;
+            }
+            std::fs::rename(&path, &expected)?;
+            info!("{} is moved to {}.", path.display(), expected.display());
+        }
+        let path = expected;
+
+        let versions = crates.entry(normalize_crate_name(&meta.name)).or_default();
+        if let Some(other) = versions
+            .iter()
+            .find(|v| v.name != meta.name || version_key(&v.version) == version_key(&meta.version))
+        {
+            warn!("{} conflicts with {}.", path.display(), other);
+            report.conflicts.push(path);
+            continue;
+        }
+        versions.push(meta);
+    }
+
+    for change in changes {
+        let version = crates
+            .get_mut(&normalize_crate_name(&change.name))
+            .and_then(|versions| versions.iter_mut().find(|v| v.version == change.version));
+        match (version, change.action) {
+            (Some(meta), ChangeAction::Yanked) => meta.yanked = Some(true),
+            (Some(meta), ChangeAction::Unyanked) => meta.yanked = Some(false),
+            // mtimes of crate files change with copies, so only the log has publish times
+            (Some(meta), ChangeAction::Published) => {
+                if change.timestamp.is_some() {
+                    meta.pubtime = change.timestamp.clone();
+                }
+            }
+            (None, _) => report
+                .unknown
+                .push(format!("{}-{}", change.name, change.version)),
+        }
+    }
+    report.unknown.sort();
+    report.unknown.dedup();
+
+    let index_path = registry.index_path();
+    if index_path.exists() {
+        let time = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut old_index = index_path.with_extension(format!("old-{}", time));
+        let mut n = 1;
+        while old_index.exists() {
+            old_index = index_path.with_extension(format!("old-{}-{}", time, n));
+            n += 1;
+        }
+        std::fs::rename(index_path, &old_index)?;
+        info!(
+            "Index {} is moved to {}.",
+            index_path.display(),
+            old_index.display()
+        );
+        report.old_index = Some(old_index);
+    }
+
+    std::fs::create_dir(index_path)?;
+    git2::Repository::init(index_path)?;
+    std::fs::write(
+        index_path.join(Registry::CONFIG_JSON_FILE),
+        serde_json::to_string_pretty(&UrlConfig::from(registry))?,
+    )?;
+
+    for mut versions in crates.into_values() {
+        // publish times are not known for every version
+        versions.sort_by(semver_order);
+        let index_file = index_path.join(get_index_path(&versions[0].name));
+        if let Some(parent) = index_file.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut file = File::create(&index_file)?;
+        for meta in versions {
+            report.versions += 1;
+            if meta.yanked() {
+                report.yanked += 1;
+            }
+            writeln!(file, "{}", serde_json::to_string(&meta)?)?;
+        }
+    }
+
+    report.commit = Some(commit_index(registry, "Rebuild index from crate files")?);
+
+    Ok(report)
+}
+
+/// Order of the versions by semver, versions which fail to parse are kept in place
+///
+fn semver_order(a: &CrateMeta, b: &CrateMeta) -> Ordering {
+    match (Version::parse(&a.version), Version::parse(&b.version)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => Ordering::Equal,
+    }
+}
+
+/// Get the path of an index file relative to the index directory, separated by `/`
+///
+fn relative_index_path(registry: &Registry, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(registry.index_path()).ok()?;
+    let parts: Option<Vec<&str>> = relative.iter().map(|s| s.to_str()).collect();
+    parts.map(|parts| parts.join("/"))
+}
+
+/// Versions which differ only in build metadata are the same version for cargo
+///
+pub fn version_key(version: &str) -> &str {
+    version.split('+').next().unwrap_or(version)
+}
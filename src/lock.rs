@@ -0,0 +1,180 @@
+use crate::error::{SkrdError, SkrdResult};
+use fs2::FileExt;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval of retrying a lock held by another writer
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lock configuration in `registry.toml`
+///
+/// .toml example:
+///
+/// ```toml
+///
+/// [lock]
+/// # seconds to wait for other writers before a "registry busy" error
+/// timeout = 10
+///
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockConfig {
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        LockConfig {
+            timeout: default_timeout(),
+        }
+    }
+}
+
+impl LockConfig {
+    pub fn is_default(&self) -> bool {
+        *self == LockConfig::default()
+    }
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// Writer holding the lock, recorded in the lock file
+#[derive(Serialize, Deserialize, Debug)]
+struct Holder {
+    pid: u32,
+    operation: String,
+    /// RFC 3339 time of acquiring
+    since: String,
+}
+
+impl Holder {
+    fn read(path: &Path) -> Option<Holder> {
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(content.trim()).ok()
+    }
+}
+
+/// Lock of a registry, which serializes the writers of its index and crates
+///
+/// Threads of a process are serialized by a flag in the registry, and processes by an
+/// advisory lock of the lock file in the registry root. The lock is released when it is
+/// dropped, or by the OS if the process exits.
+///
+pub struct RegistryLock {
+    file: File,
+    _held: Held,
+}
+
+impl RegistryLock {
+    /// Acquire the lock for `operation`, waits at most `config.timeout` seconds for
+    /// other writers
+    ///
+    pub fn acquire(
+        path: &Path,
+        held: &Arc<Mutex<bool>>,
+        name: &str,
+        operation: &str,
+        config: &LockConfig,
+    ) -> SkrdResult<Self> {
+        let deadline = Instant::now() + Duration::from_secs(config.timeout);
+
+        let held = loop {
+            if let Some(held) = Held::try_acquire(held)? {
+                break held;
+            }
+            if Instant::now() >= deadline {
+                return Err(busy(path, name));
+            }
+            std::thread::sleep(RETRY_INTERVAL);
+        };
+
+        // a lock held by the OS is never taken over, its holder may run in another PID
+        // namespace, e.g. a container sharing the registry volume
+        let mut file = open(path)?;
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => break,
+                Err(ref e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                    if Instant::now() >= deadline {
+                        return Err(busy(path, name));
+                    }
+                    std::thread::sleep(RETRY_INTERVAL);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // the record is cleared on release, so it is left by a process which exited
+        if let Some(holder) = Holder::read(path) {
+            warn!(
+                "Lock of registry '{}' left by {} (pid {}) since {} is taken over.",
+                name, holder.operation, holder.pid, holder.since
+            );
+        }
+
+        let holder = Holder {
+            pid: std::process::id(),
+            operation: operation.to_owned(),
+            since: chrono::Local::now().to_rfc3339(),
+        };
+        file.set_len(0)?;
+        file.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        file.sync_data()?;
+
+        Ok(RegistryLock { file, _held: held })
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+impl Drop for RegistryLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.set_len(0).and_then(|_| self.file.unlock()) {
+            warn!("Registry lock release error: {}", e);
+        }
+    }
+}
+
+/// Flag of the registry set by the thread holding the lock, cleared when it is dropped
+struct Held(Arc<Mutex<bool>>);
+
+impl Held {
+    fn try_acquire(flag: &Arc<Mutex<bool>>) -> SkrdResult<Option<Held>> {
+        let mut held = flag.lock()?;
+        if *held {
+            return Ok(None);
+        }
+        *held = true;
+        Ok(Some(Held(flag.clone())))
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = false;
+    }
+}
+
+fn busy(path: &Path, name: &str) -> SkrdError {
+    match Holder::read(path) {
+        Some(holder) => SkrdError::Busy(format!(
+            "registry '{}' is locked by {} (pid {}) since {}",
+            name, holder.operation, holder.pid, holder.since
+        )),
+        None => SkrdError::Busy(format!("registry '{}' is locked by another writer", name)),
+    }
+}
@@ -6,17 +6,29 @@ extern crate log;
 
 use structopt::StructOpt;
 
+mod archive;
+mod backup;
+mod bundle;
 mod command;
+mod diff;
 use command::Command;
 
 mod error;
-use crate::error::{SkrdError, SkrdResult};
+mod export;
+mod feed;
+mod import;
+mod index;
+use crate::error::SkrdResult;
 
+mod lock;
 mod logger;
 use crate::logger::LoggerGuard;
 use slog::Level;
 
+mod overlay;
 mod registry;
+mod replica;
+mod storage;
 mod util;
 
 fn main() -> SkrdResult<()> {
@@ -27,20 +39,30 @@ fn main() -> SkrdResult<()> {
     match command {
         // private registry
         Command::Create(create) => create.create(),
+        Command::Import(import) => import.import(),
 
         // mirroring
         Command::Mirror(mirror) => mirror.mirror(),
         Command::Update(update) => update.update(),
+        Command::Virtual(registry) => registry.create(),
+        Command::Replica(replica) => replica.create(),
+
+        // maintenance
+        Command::Index(index) => index.index(),
+        Command::Storage(storage) => storage.storage(),
+        Command::Backup(backup) => backup.backup(),
+        Command::Restore(restore) => restore.restore(),
 
         // server
         Command::Serve(serve) => serve.serve(),
 
         // migration
-        Command::Package(_pack) => {
-            Err(SkrdError::StaticCustom("Subcommand pack is unimplemented!"))
-        }
+        Command::Package(package) => package.package(),
+        Command::Unpackage(unpackage) => unpackage.unpackage(),
+        Command::ExportStatic(export) => export.export_static(),
+        Command::ExportLocal(export) => export.export_local(),
 
         // command line tool
-        Command::Execute(_exec) => Err(SkrdError::StaticCustom("Subcommand exec is unimplemented")),
+        Command::Execute(exec) => exec.execute(),
     }
 }
@@ -0,0 +1,189 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::export::write_if_changed;
+use crate::index::{commit_index, index_files};
+use crate::registry::Registry;
+use crate::util::{get_index_path, is_valid_crate_name};
+use git2::Oid;
+use rayon::prelude::*;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+/// Backing registries of a virtual registry, in the order of priority
+///
+/// It is empty if the registry is not virtual.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Overlay {
+    registries: Vec<Registry>,
+}
+
+impl Overlay {
+    pub fn open(registry: &Registry) -> SkrdResult<Self> {
+        let registries = match registry.virtual_config() {
+            Some(config) => config.open_registries(registry.root())?,
+            None => Vec::new(),
+        };
+
+        Ok(Overlay { registries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registries.is_empty()
+    }
+
+    pub fn registries(&self) -> &[Registry] {
+        &self.registries
+    }
+
+    /// Get the backing registry which owns crate `name`, it is the first one with the crate
+    /// in its index, the same as `merge_index` does
+    ///
+    pub fn owner(&self, name: &str) -> Option<&Registry> {
+        if !is_valid_crate_name(name) {
+            return None;
+        }
+        let path = get_index_path(name);
+        self.registries
+            .iter()
+            .find(|registry| registry.index_path().join(&path).is_file())
+    }
+}
+
+/// A crate in more than one backing registry
+#[derive(Debug, Clone)]
+pub struct Collision {
+    pub name: String,
+    /// Name of the registry whose crate is in the merged index
+    pub owner: String,
+    /// Name of the registry whose crate is hidden
+    pub hidden: String,
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "crate `{}` of '{}' hides the one of '{}'",
+            self.name, self.owner, self.hidden
+        )
+    }
+}
+
+/// Result of `merge_index`
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Index files in the merged index
+    pub files: usize,
+    /// Index files written
+    pub updated: usize,
+    /// Index files removed because no backing registry has them anymore
+    pub removed: usize,
+    pub collisions: Vec<Collision>,
+    /// `None` if the merged index is not changed
+    pub commit: Option<Oid>,
+}
+
+/// Merge the indexes of the backing registries into the index of the virtual `registry`
+///
+/// Crates are compared by the lowercased name, and a crate in an earlier backing registry
+/// hides all versions of the same crate in later ones. Nothing is committed if an index
+/// file fails to be merged.
+///
+pub fn merge_index(registry: &Registry) -> SkrdResult<MergeReport> {
+    let overlay = Overlay::open(registry)?;
+    if overlay.is_empty() {
+        return Err(SkrdError::Custom(format!(
+            "registry '{}' is not a virtual registry",
+            registry.config().name()
+        )));
+    }
+
+    let mut report = MergeReport::default();
+
+    // relative path in index => (backing registry, index file)
+    let mut owners = BTreeMap::new();
+    for (i, backing) in overlay.registries().iter().enumerate() {
+        for path in index_files(backing) {
+            let relative = match path.strip_prefix(backing.index_path()) {
+                Ok(relative) => relative.to_string_lossy().to_ascii_lowercase(),
+                Err(_) => continue,
+            };
+
+            match owners.entry(relative) {
+                Entry::Vacant(entry) => {
+                    entry.insert((i, path));
+                }
+                Entry::Occupied(entry) => {
+                    let (owner, _) = entry.get();
+                    report.collisions.push(Collision {
+                        name: path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                        owner: overlay.registries()[*owner].config().name().to_owned(),
+                        hidden: backing.config().name().to_owned(),
+                    });
+                }
+            }
+        }
+    }
+    report.files = owners.len();
+
+    let written: Vec<_> = owners
+        .par_iter()
+        .map(|(relative, (_, path))| {
+            std::fs::read(path)
+                .map_err(SkrdError::from)
+                .and_then(|content| {
+                    write_if_changed(&registry.index_path().join(relative), &content)
+                })
+                .map_err(|e| (path, e))
+        })
+        .collect();
+    // the merged index is not committed with stale or missing crates
+    let mut failed = 0;
+    for result in written {
+        match result {
+            Ok(written) => report.updated += written as usize,
+            Err((path, e)) => {
+                error!("Index file {} merge error: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(SkrdError::Custom(format!(
+            "{} index files of registry '{}' failed to be merged",
+            failed,
+            registry.config().name()
+        )));
+    }
+
+    let stale: Vec<PathBuf> = index_files(registry)
+        .filter(|path| {
+            path.strip_prefix(registry.index_path())
+                .map(|relative| !owners.contains_key(&*relative.to_string_lossy()))
+                .unwrap_or(false)
+        })
+        .collect();
+    for path in &stale {
+        std::fs::remove_file(path)?;
+    }
+    report.removed = stale.len();
+
+    if report.updated + report.removed > 0 {
+        let names: Vec<_> = overlay
+            .registries()
+            .iter()
+            .map(|r| format!("'{}'", r.config().name()))
+            .collect();
+        report.commit = Some(commit_index(
+            registry,
+            &format!("Merge index of {}", names.join(", ")),
+        )?);
+    }
+
+    Ok(report)
+}
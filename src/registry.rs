@@ -1,26 +1,44 @@
 use crate::error::{SkrdError, SkrdResult};
+use crate::index::read_index_file;
+use crate::lock::{LockConfig, RegistryLock};
+use crate::replica::ReplicaConfig;
+use crate::storage::{Layout, Storage, StorageConfig};
+use crate::util::{
+    get_index_path, is_valid_crate_name, normalize_crate_name, write_lower_prefix_marker,
+};
 use rustls::internal::pemfile::{certs, pkcs8_private_keys};
 use rustls::{NoClientAuth, ServerConfig};
+use serde::Deserializer;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{self, Display};
 use std::fs::OpenOptions;
 use std::io::{BufReader, Write};
 use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::{fs::File, io::Read, path::PathBuf};
 
+/// Index of crates.io, the default source to be mirrored
+pub const CRATES_IO_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+
 /// Registry
 ///
 /// Directory structure:
 ///
 /// root
 ///   ├─registry.toml
+///   ├─registry.lock
 ///   ├─index
 ///   │  ├─.git
 ///   │  └─ ...
 ///   └─crates
 ///      └─ ...
 ///
+/// Crate files are stored in `crates`, unless another storage is configured.
+/// Writers of the index and crates hold the lock of `registry.lock`, see `Registry::lock`.
+///
 #[derive(Debug, Clone)]
 pub struct Registry {
     root: PathBuf,
@@ -28,6 +46,9 @@ pub struct Registry {
     index_path: PathBuf,
     index_git_path: PathBuf,
     crates_path: PathBuf,
+    storage: Arc<dyn Storage>,
+    /// Whether a thread of this process holds the registry lock
+    lock: Arc<Mutex<bool>>,
 }
 
 impl Registry {
@@ -36,6 +57,9 @@ impl Registry {
     pub const CONFIG_JSON_FILE: &'static str = "config.json";
     pub const CRATES_DIRECTORY: &'static str = "crates";
     pub const REGISTRY_TOML_FILE: &'static str = "registry.toml";
+    pub const LOCK_FILE: &'static str = "registry.lock";
+    /// Crate files stored by earlier versions are moved, see `migrate_legacy_crate_files`
+    pub const LOWER_PREFIX_FILE: &'static str = "lowerprefix.done";
 
     pub fn open<P: Into<PathBuf>>(root: P) -> SkrdResult<Self> {
         let root = root.into();
@@ -48,6 +72,8 @@ impl Registry {
         let registry = Registry {
             index_git_path: index_path.join(Registry::INDEX_GIT_DIRECTORY),
             index_path,
+            storage: config.storage.open(&root, &crates_path)?,
+            lock: Arc::default(),
             crates_path,
 
             root,
@@ -81,6 +107,8 @@ impl Registry {
         let registry = Registry {
             index_path,
             index_git_path,
+            storage: config.storage.open(&root, &crates_path)?,
+            lock: Arc::default(),
             crates_path,
             root,
             config,
@@ -110,6 +138,46 @@ impl Registry {
         let registry = Registry {
             index_path,
             index_git_path,
+            storage: config.storage.open(&root, &crates_path)?,
+            lock: Arc::default(),
+            crates_path,
+            root,
+            config,
+        };
+
+        Ok(registry)
+    }
+
+    /// Create a virtual registry, whose index is merged from the `backing` registries
+    ///
+    pub fn create_virtual<P: Into<PathBuf>>(
+        root: P,
+        name: &str,
+        backing: Virtual,
+    ) -> SkrdResult<Self> {
+        let root = root.into();
+
+        let (index_path, crates_path) = create_registry_dirs(&root)?;
+
+        let toml_path = root.join(Registry::REGISTRY_TOML_FILE);
+        let mut config = RegistryConfig::create(name);
+        config.virtual_registry = Some(backing);
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&toml_path)?;
+        let toml = toml::to_string_pretty(&config)?;
+        file.write_all(toml.as_bytes())?;
+        drop(file);
+        info!("Registry config file {} is created.", toml_path.display());
+
+        let index_git_path = index_path.join(Registry::INDEX_GIT_DIRECTORY);
+
+        let registry = Registry {
+            index_path,
+            index_git_path,
+            storage: config.storage.open(&root, &crates_path)?,
+            lock: Arc::default(),
             crates_path,
             root,
             config,
@@ -118,9 +186,9 @@ impl Registry {
         Ok(registry)
     }
 
-    //    pub fn root(&self) -> &PathBuf {
-    //        &self.root
-    //    }
+    pub fn root(&self) -> &PathBuf {
+        &self.root
+    }
 
     pub fn index_path(&self) -> &PathBuf {
         &self.index_path
@@ -134,10 +202,85 @@ impl Registry {
         &self.crates_path
     }
 
+    /// Storage of crate files, paths in it are built by `crate_path`
+    ///
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// Lock the registry for `operation`, which writes the index or crate files
+    ///
+    /// Returns a "registry busy" error if another writer holds the lock longer than the
+    /// timeout in `registry.toml`.
+    ///
+    pub fn lock(&self, operation: &str) -> SkrdResult<RegistryLock> {
+        RegistryLock::acquire(
+            &self.root.join(Registry::LOCK_FILE),
+            &self.lock,
+            self.config.name(),
+            operation,
+            &self.config.lock,
+        )
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.config.storage.layout
+    }
+
+    /// Path of the crate file of a version in the storage
+    ///
+    pub fn crate_path(&self, meta: &CrateMeta) -> String {
+        self.layout()
+            .crate_path(&meta.name, &meta.version, &meta.checksum)
+    }
+
+    /// Versions of crate `name` in the index, any casing of the name finds them
+    ///
+    /// Names of the versions are the ones published, so they are used to build paths of
+    /// crate files. Invalid names find nothing.
+    ///
+    pub fn find_versions(&self, name: &str) -> SkrdResult<Vec<CrateMeta>> {
+        if !is_valid_crate_name(name) {
+            return Ok(Vec::new());
+        }
+
+        let index_file = self.index_path.join(get_index_path(name));
+        if !index_file.is_file() {
+            return Ok(Vec::new());
+        }
+
+        let name = normalize_crate_name(name);
+        Ok(read_index_file(index_file)?
+            .into_iter()
+            .filter(|meta| normalize_crate_name(&meta.name) == name)
+            .collect())
+    }
+
+    /// Index entry of `name`-`version`, any casing of the name finds it
+    ///
+    pub fn find_crate(&self, name: &str, version: &str) -> SkrdResult<Option<CrateMeta>> {
+        Ok(self
+            .find_versions(name)?
+            .into_iter()
+            .find(|meta| meta.version == version))
+    }
+
     pub fn config(&self) -> &RegistryConfig {
         &self.config
     }
 
+    pub fn config_mut(&mut self) -> &mut RegistryConfig {
+        &mut self.config
+    }
+
+    /// Write the configuration back to `registry.toml`
+    ///
+    pub fn save_config(&self) -> SkrdResult<()> {
+        let toml = toml::to_string_pretty(&self.config)?;
+        std::fs::write(self.root.join(Registry::REGISTRY_TOML_FILE), toml)?;
+        Ok(())
+    }
+
     pub fn mirror_config(&self) -> Option<&Mirror> {
         match &self.config.mirror {
             Some(mirror) => Some(mirror),
@@ -145,6 +288,20 @@ impl Registry {
         }
     }
 
+    pub fn virtual_config(&self) -> Option<&Virtual> {
+        self.config.virtual_registry.as_ref()
+    }
+
+    pub fn replica_config(&self) -> Option<&ReplicaConfig> {
+        self.config.replica.as_ref()
+    }
+
+    /// Url of the index repository, which is used by cargo as the registry url
+    ///
+    pub fn index_url(&self) -> String {
+        format!("{}/{}", self.base_url(), Registry::INDEX_DIRECTORY)
+    }
+
     pub fn base_url(&self) -> String {
         let config = self.config();
         format!(
@@ -173,6 +330,9 @@ fn create_registry_dirs(root: &PathBuf) -> SkrdResult<(PathBuf, PathBuf)> {
     std::fs::create_dir(&crates_path)?;
     info!("Crates path {} is created.", crates_path.display());
 
+    // no crate file of a new registry is placed by earlier versions
+    write_lower_prefix_marker(root)?;
+
     Ok((index_path, crates_path))
 }
 
@@ -207,12 +367,34 @@ impl FromStr for Registry {
 /// git-upload-pack = false
 ///
 /// ```
+///
+/// A virtual registry has a `virtual` section instead of `mirror`:
+///
+/// ```toml
+///
+/// [virtual]
+/// registries = ["../private", "../mirror"]
+///
+/// ```
+///
+/// Crate files can be kept in an object store with a `storage` section, see `StorageConfig`.
+///
+/// A replica of another silkroad registry has a `replica` section, see `ReplicaConfig`.
+///
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RegistryConfig {
     meta: Meta,
     mirror: Option<Mirror>,
+    #[serde(rename = "virtual")]
+    virtual_registry: Option<Virtual>,
     http: HttpConfig,
     access: AccessControl,
+    #[serde(default, skip_serializing_if = "StorageConfig::is_default")]
+    storage: StorageConfig,
+    #[serde(default, skip_serializing_if = "LockConfig::is_default")]
+    lock: LockConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replica: Option<ReplicaConfig>,
 }
 
 impl RegistryConfig {
@@ -230,8 +412,12 @@ impl RegistryConfig {
                 name: name.to_owned(),
             },
             mirror: None,
+            virtual_registry: None,
             http: HttpConfig::default(),
             access: AccessControl::default(),
+            storage: StorageConfig::default(),
+            lock: LockConfig::default(),
+            replica: None,
         }
     }
 
@@ -241,11 +427,29 @@ impl RegistryConfig {
                 name: name.to_owned(),
             },
             mirror: Some(mirror),
+            virtual_registry: None,
             http: HttpConfig::default(),
             access: AccessControl::default(),
+            storage: StorageConfig::default(),
+            lock: LockConfig::default(),
+            replica: None,
         }
     }
 
+    /// Config of a registry imported from a bundle of this one
+    ///
+    /// Only the name, the mirror source and the crate storage layout are kept, crate files
+    /// of the bundle are in the `crates` directory.
+    ///
+    pub fn imported(&self) -> Self {
+        let mut config = match &self.mirror {
+            Some(mirror) => RegistryConfig::mirror(self.name(), mirror.clone()),
+            None => RegistryConfig::create(self.name()),
+        };
+        config.storage.layout = self.storage.layout;
+        config
+    }
+
     pub fn name(&self) -> &str {
         &self.meta.name
     }
@@ -254,10 +458,6 @@ impl RegistryConfig {
         &self.http.domain
     }
 
-    pub fn ip(&self) -> IpAddr {
-        self.http.ip
-    }
-
     pub fn port(&self) -> u16 {
         self.http.port
     }
@@ -266,19 +466,57 @@ impl RegistryConfig {
         self.http.ssl
     }
 
-    pub fn receive_on(&self) -> bool {
-        self.access.receive
+    pub fn http(&self) -> &HttpConfig {
+        &self.http
     }
 
-    pub fn upload_on(&self) -> bool {
-        self.access.upload
+    /// Replace the http config, e.g. when the registry is served with others
+    ///
+    pub fn set_http(&mut self, http: HttpConfig) {
+        self.http = http;
+    }
+
+    pub fn set_replica(&mut self, replica: ReplicaConfig) {
+        self.replica = Some(replica);
+    }
+
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    pub fn set_access(&mut self, access: AccessControl) {
+        self.access = access;
+    }
+
+    pub fn storage(&self) -> &StorageConfig {
+        &self.storage
+    }
+
+    /// Replace the storage config, the registry should be opened again to use it
+    ///
+    pub fn set_storage(&mut self, storage: StorageConfig) {
+        self.storage = storage;
+    }
+}
+
+impl HttpConfig {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn ssl(&self) -> bool {
+        self.ssl
     }
 
     pub fn build_ssl_config(&self) -> SkrdResult<ServerConfig> {
         let mut config = ServerConfig::new(NoClientAuth::new());
 
-        let cert_file = &mut BufReader::new(File::open(&self.http.cert)?);
-        let key_file = &mut BufReader::new(File::open(&self.http.key)?);
+        let cert_file = &mut BufReader::new(File::open(&self.cert)?);
+        let key_file = &mut BufReader::new(File::open(&self.key)?);
         let cert_chain = certs(cert_file)
             .map_err(|_| rustls::TLSError::General("Extract certificates error".to_owned()))?;
         let mut keys = pkcs8_private_keys(key_file)
@@ -289,6 +527,79 @@ impl RegistryConfig {
     }
 }
 
+///
+/// Configuration of a server which serves several registries on one port
+///
+/// Each registry is served under `/{name}` with its own access control, which can be
+/// overridden here. The `http` section replaces the ones in `registry.toml`.
+///
+/// .toml example:
+///
+/// ```toml
+///
+/// [http]
+/// domain = "crates.example.com"
+/// ip = "0.0.0.0"
+/// port = 443
+/// ssl = true
+/// cert = "path/to/cert.pem"
+/// key = "path/to/key.pem"
+///
+/// [[registries]]
+/// path = "mirror"
+///
+/// [[registries]]
+/// path = "private"
+/// access = { git-receive-pack = true, git-upload-pack = true }
+///
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServeConfig {
+    pub http: HttpConfig,
+    pub registries: Vec<ServedRegistry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServedRegistry {
+    /// Registry root, relative to the directory of the config file
+    pub path: PathBuf,
+    pub access: Option<AccessControl>,
+}
+
+impl ServeConfig {
+    /// Read the config file and open all registries in it
+    ///
+    pub fn open<P: Into<PathBuf>>(path: P) -> SkrdResult<(Self, Vec<Registry>)> {
+        let path = path.into();
+        let mut content = String::new();
+        File::open(&path)?.read_to_string(&mut content)?;
+        let config = toml::from_str::<ServeConfig>(&content)?;
+
+        let base = path.parent().map(PathBuf::from).unwrap_or_default();
+        let mut registries: Vec<Registry> = Vec::with_capacity(config.registries.len());
+        for served in &config.registries {
+            let mut registry = Registry::open(base.join(&served.path))?;
+            if registries
+                .iter()
+                .any(|r| r.config().name() == registry.config().name())
+            {
+                return Err(SkrdError::Custom(format!(
+                    "registry name '{}' is used more than once",
+                    registry.config().name()
+                )));
+            }
+
+            registry.config_mut().set_http(config.http.clone());
+            if let Some(access) = &served.access {
+                registry.config_mut().set_access(access.clone());
+            }
+            registries.push(registry);
+        }
+
+        Ok((config, registries))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Meta {
     name: String,
@@ -332,6 +643,34 @@ impl Mirror {
     }
 }
 
+/// Backing registries of a virtual registry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Virtual {
+    /// Registry roots, relative to the root of the virtual registry.
+    /// A crate in an earlier registry hides the crate with the same name in later ones.
+    pub registries: Vec<PathBuf>,
+}
+
+impl Virtual {
+    /// Open the backing registries of the virtual registry at `root`, in the order of priority
+    ///
+    pub fn open_registries(&self, root: &Path) -> SkrdResult<Vec<Registry>> {
+        let mut registries = Vec::with_capacity(self.registries.len());
+        for path in &self.registries {
+            let registry = Registry::open(root.join(path))?;
+            if registry.virtual_config().is_some() {
+                return Err(SkrdError::Custom(format!(
+                    "{} is a virtual registry, which can not back another one",
+                    path.display()
+                )));
+            }
+            registries.push(registry);
+        }
+
+        Ok(registries)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HttpConfig {
     domain: String,
@@ -355,12 +694,39 @@ impl Default for HttpConfig {
     }
 }
 
+/// Access control of a served registry
+///
+/// In the `read-only` or `maintenance` mode, downloads and index fetches are served while
+/// publish, yank, owner changes and `git-receive-pack` are refused.
+///
+/// The change feed is only served to clients with one of the `feed-tokens`, it is disabled
+/// if there is no token.
+///
+/// .toml example:
+///
+/// ```toml
+///
+/// [access]
+/// git-receive-pack = true
+/// git-upload-pack = true
+/// # optional
+/// read-only = false
+/// maintenance = false
+/// feed-tokens = ["3b2c0d6f9a4e..."]
+///
+/// ```
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AccessControl {
     #[serde(rename = "git-receive-pack")]
     receive: bool,
     #[serde(rename = "git-upload-pack")]
     upload: bool,
+    #[serde(rename = "read-only", default)]
+    read_only: bool,
+    #[serde(default)]
+    maintenance: bool,
+    #[serde(rename = "feed-tokens", default, skip_serializing_if = "Vec::is_empty")]
+    feed_tokens: Vec<String>,
 }
 
 impl Default for AccessControl {
@@ -368,33 +734,220 @@ impl Default for AccessControl {
         AccessControl {
             receive: true,
             upload: true,
+            read_only: false,
+            maintenance: false,
+            feed_tokens: Vec::new(),
         }
     }
 }
 
+impl AccessControl {
+    pub fn receive_on(&self) -> bool {
+        self.receive
+    }
+
+    pub fn upload_on(&self) -> bool {
+        self.upload
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn maintenance(&self) -> bool {
+        self.maintenance
+    }
+
+    pub fn feed_on(&self) -> bool {
+        !self.feed_tokens.is_empty()
+    }
+
+    /// `token` is allowed to read the change feed
+    ///
+    /// Every token is compared in constant time, so the time taken does not tell how
+    /// much of a token is guessed.
+    ///
+    pub fn feed_allowed(&self, token: &str) -> bool {
+        self.feed_tokens.iter().fold(false, |allowed, feed_token| {
+            allowed | constant_time_eq(feed_token.as_bytes(), token.as_bytes())
+        })
+    }
+}
+
+/// Compare the bytes of `a` and `b` without returning at the first different one
+///
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Content of `config.json` in the index
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct UrlConfig {
     pub dl: String,
-    pub api: String,
+    /// `None` if the registry has no web API, e.g. a static site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
 }
 
+/// Crate files are downloaded from the `crates` url with the template of the layout,
+/// except in virtual registries, in which the web API finds the backing registry
+///
 impl From<&Registry> for UrlConfig {
     fn from(registry: &Registry) -> Self {
+        let dl = if registry.virtual_config().is_some() {
+            format!("{}{}", registry.base_url(), "/api/v1/crates")
+        } else {
+            format!(
+                "{}/{}/{}",
+                registry.base_url(),
+                Registry::CRATES_DIRECTORY,
+                registry.layout().dl_template()
+            )
+        };
+
         UrlConfig {
-            dl: format!("{}{}", registry.base_url(), "/api/v1/crates"),
-            api: registry.base_url(),
+            dl,
+            api: Some(registry.base_url()),
         }
     }
 }
 
+/// A line of an index file, which describes a version of a crate
+///
+/// Fields are declared in the order crates.io writes them. Fields absent in a line are
+/// `None`, and nullable fields keep whether they were absent or `null`, so a parsed line
+/// is serialized to the same JSON. Unknown fields are kept in `extra` in their order and
+/// appended at the end.
+///
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct CrateMeta {
     pub name: String,
     #[serde(rename = "vers")]
     pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deps: Option<Vec<Dependency>>,
     #[serde(rename = "cksum", with = "hex_serde")]
     pub checksum: [u8; 32],
-    pub yanked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<BTreeMap<String, Vec<String>>>,
+    /// Features using `dep:` or `?` syntax, only present with `v` = 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features2: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yanked: Option<bool>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub links: Option<Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubtime: Option<String>,
+    /// Version of the index entry format, `None` means 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v: Option<u32>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CrateMeta {
+    /// Parse a line of an index file
+    ///
+    pub fn from_line(line: &str) -> SkrdResult<Self> {
+        Ok(serde_json::from_str(line)?)
+    }
+
+    pub fn deps(&self) -> &[Dependency] {
+        self.deps.as_deref().unwrap_or_default()
+    }
+
+    pub fn yanked(&self) -> bool {
+        self.yanked.unwrap_or(false)
+    }
+
+    /// Get the dependencies on crate `name` in this registry
+    ///
+    pub fn deps_on<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Dependency> {
+        self.deps().iter().filter(move |dep| {
+            dep.registry.is_none() && dep.crate_name().eq_ignore_ascii_case(name)
+        })
+    }
+}
+
+/// A dependency of a crate version in index
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Dependency {
+    /// Name used in `Cargo.toml`, may be renamed
+    pub name: String,
+    pub req: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optional: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_features: Option<bool>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub target: Option<Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<DependencyKind>,
+    /// `None` if it is in the same registry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// The actual crate name if the dependency is renamed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Dependency {
+    pub fn crate_name(&self) -> &str {
+        self.package.as_ref().unwrap_or(&self.name)
+    }
+
+    pub fn optional(&self) -> bool {
+        self.optional.unwrap_or(false)
+    }
+
+    pub fn default_features(&self) -> bool {
+        self.default_features.unwrap_or(true)
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target
+            .as_ref()
+            .and_then(|t| t.as_ref())
+            .map(String::as_str)
+    }
+
+    pub fn kind(&self) -> DependencyKind {
+        self.kind.unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    #[default]
+    Normal,
+    Dev,
+    Build,
+}
+
+/// Deserialize a field which may be absent (`None`) or `null` (`Some(None)`)
+///
+fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
 }
 
 impl Display for CrateMeta {
@@ -410,3 +963,79 @@ fn is_default_port(port: u16, ssl: bool) -> bool {
         port == 80u16
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CKSUM: &str = "de437e2a6208b014ab52972a27e59b33fa2920d3e00fe05026167a1c509d19cc";
+
+    fn round_trip(line: &str) -> String {
+        serde_json::to_string(&CrateMeta::from_line(line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn crates_io_lines_round_trip() {
+        let lines = [
+            format!(
+                r#"{{"name":"volatile-register","vers":"0.1.0","deps":[],"cksum":"{}","features":{{}},"yanked":false,"pubtime":"2016-09-27T22:57:17Z"}}"#,
+                CKSUM
+            ),
+            format!(
+                r#"{{"name":"volatile-register","vers":"0.2.2","deps":[{{"name":"vcell","req":"^0.1.0","features":[],"optional":false,"default_features":true,"target":null,"kind":"normal"}}],"cksum":"{}","features":{{}},"yanked":false,"pubtime":"2023-10-20T23:51:10Z"}}"#,
+                CKSUM
+            ),
+            format!(
+                r#"{{"name":"serde","vers":"1.0.200","deps":[{{"name":"serde_derive","req":"^1","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal"}},{{"name":"serde_derive","req":"=1.0.200","features":[],"optional":false,"default_features":true,"target":"cfg(any())","kind":"normal"}}],"cksum":"{}","features":{{"alloc":[],"default":["std"],"std":[]}},"features2":{{"derive":["dep:serde_derive"]}},"yanked":true,"links":null,"rust_version":"1.31","v":2}}"#,
+                CKSUM
+            ),
+        ];
+
+        for line in &lines {
+            assert_eq!(&round_trip(line), line);
+        }
+    }
+
+    #[test]
+    fn absent_and_unknown_fields_round_trip() {
+        let line = format!(
+            r#"{{"name":"foo","vers":"0.1.0","deps":[{{"name":"bar","req":"^1","registry":"https://example.com/index","zeta":true,"alpha":1}}],"cksum":"{}","zeta":{{"b":1,"a":2}},"alpha":[]}}"#,
+            CKSUM
+        );
+        assert_eq!(round_trip(&line), line);
+
+        let meta = CrateMeta::from_line(&line).unwrap();
+        assert!(!meta.yanked());
+        assert!(meta.deps()[0].default_features());
+        assert!(!meta.deps()[0].optional());
+    }
+
+    #[test]
+    fn yank_only_changes_yanked() {
+        let line = format!(
+            r#"{{"name":"foo","vers":"0.1.0","deps":[],"cksum":"{}","features":{{}},"yanked":false,"links":null,"custom":"kept"}}"#,
+            CKSUM
+        );
+        let mut meta = CrateMeta::from_line(&line).unwrap();
+        meta.yanked = Some(true);
+        assert_eq!(
+            serde_json::to_string(&meta).unwrap(),
+            line.replace(r#""yanked":false"#, r#""yanked":true"#)
+        );
+
+        // a line without `yanked` gets it at its place of crates.io
+        let line = format!(
+            r#"{{"name":"foo","vers":"0.1.0","cksum":"{}","custom":1}}"#,
+            CKSUM
+        );
+        let mut meta = CrateMeta::from_line(&line).unwrap();
+        meta.yanked = Some(true);
+        assert_eq!(
+            serde_json::to_string(&meta).unwrap(),
+            format!(
+                r#"{{"name":"foo","vers":"0.1.0","cksum":"{}","yanked":true,"custom":1}}"#,
+                CKSUM
+            )
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use crate::error::{SkrdError, SkrdResult};
+use crate::feed::{changes_between, is_ancestor, ChangePage};
+use crate::index::{index_files, read_index_file, ChangeAction};
+use crate::registry::{CrateMeta, Registry};
+use crate::util::write_config_json;
+use digest::Digest;
+use git2::{Direction, Oid, Remote, Repository, ResetType};
+use rayon::prelude::*;
+use reqwest::{Client, StatusCode};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Seconds a request of the change feed waits for changes of the primary
+pub const FEED_WAIT: u64 = 60;
+
+/// Interval of retrying after a failed synchronization or an unreachable primary
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Index commits fetched from the primary
+const PRIMARY_REF: &str = "refs/replica/primary";
+
+/// Index commit of the primary of which all crate files are downloaded
+const SYNCED_REF: &str = "refs/replica/synced";
+
+/// Replica configuration in `registry.toml`, a replica follows another silkroad registry
+///
+/// .toml example:
+///
+/// ```toml
+///
+/// [replica]
+/// # base url of the primary registry, the `api` url in its config.json
+/// primary = "http://crates.example.com/mymirror"
+/// # optional, forward publish, yank and owner changes to the primary instead of refusing them
+/// forward-writes = false
+/// # optional, a token in the `feed-tokens` of the primary
+/// token = "3b2c0d6f9a4e..."
+///
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplicaConfig {
+    pub primary: String,
+    #[serde(rename = "forward-writes", default)]
+    pub forward_writes: bool,
+    /// Token of the change feed of the primary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl ReplicaConfig {
+    pub fn new(primary: &str) -> Self {
+        ReplicaConfig {
+            primary: primary.trim_end_matches('/').to_owned(),
+            forward_writes: false,
+            token: None,
+        }
+    }
+
+    pub fn index_url(&self) -> String {
+        format!("{}/{}", self.primary, Registry::INDEX_DIRECTORY)
+    }
+
+    pub fn changes_url(&self) -> String {
+        format!("{}/api/v1/changes", self.primary)
+    }
+
+    /// The download API redirects to the crate file in any layout or storage of the primary
+    ///
+    fn download_url(&self, meta: &CrateMeta) -> String {
+        format!(
+            "{}/api/v1/crates/{}/{}/download",
+            self.primary, meta.name, meta.version
+        )
+    }
+}
+
+/// Result of `sync`
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Index commits of the primary before and after the synchronization, `None` if the
+    /// index is not changed
+    pub index: Option<(Option<Oid>, Oid)>,
+    pub checked: usize,
+    pub downloaded: usize,
+    pub bytes: u64,
+    pub failed: Vec<String>,
+}
+
+/// Pull the index commits of the primary, and download the crate files of new versions
+///
+/// The index is reset to the one of the primary, then `config.json` of the replica is
+/// committed on top of it. Only versions published since the last synchronization are
+/// checked, unless crate files failed to be downloaded or the history of the primary is
+/// rewritten.
+///
+pub fn sync(registry: &Registry, client: &Client) -> SkrdResult<SyncReport> {
+    let replica = replica_config(registry)?;
+    let repo = Repository::open(registry.index_path())?;
+    let mut report = SyncReport::default();
+
+    let old = repo.refname_to_id(PRIMARY_REF).ok();
+    let mut remote = repo.remote_anonymous(&replica.index_url())?;
+    let branch = primary_branch(&mut remote)?;
+    remote.fetch(&[&format!("+{}:{}", branch, PRIMARY_REF)], None, None)?;
+    let new = repo.refname_to_id(PRIMARY_REF)?;
+
+    let head = repo.head().ok().and_then(|head| head.target());
+    if !head.is_some_and(|head| is_ancestor(&repo, new, head)) {
+        repo.reset(repo.find_commit(new)?.as_object(), ResetType::Hard, None)?;
+        write_config_json(registry)?;
+        report.index = Some((old, new));
+    }
+
+    let versions = match repo.refname_to_id(SYNCED_REF) {
+        Ok(synced) if synced == new => Vec::new(),
+        Ok(synced) if is_ancestor(&repo, synced, new) => {
+            let mut versions = Vec::new();
+            for change in changes_between(&repo, synced, new)? {
+                if change.action != ChangeAction::Published {
+                    continue;
+                }
+                if let Some(meta) = registry.find_crate(&change.name, &change.version)? {
+                    versions.push(meta);
+                }
+            }
+            versions
+        }
+        _ => {
+            let mut versions = Vec::new();
+            for path in index_files(registry) {
+                versions.extend(read_index_file(&path)?);
+            }
+            versions
+        }
+    };
+
+    let results: Vec<(CrateMeta, SkrdResult<Option<u64>>)> = versions
+        .into_par_iter()
+        .map(|meta| {
+            let result = download(registry, replica, client, &meta);
+            (meta, result)
+        })
+        .collect();
+    for (meta, result) in results {
+        report.checked += 1;
+        match result {
+            Ok(Some(len)) => {
+                report.downloaded += 1;
+                report.bytes += len;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Crate {} download error: {}", meta, e);
+                report.failed.push(meta.to_string());
+            }
+        }
+    }
+
+    // failed versions are checked again by the next synchronization
+    if report.failed.is_empty() {
+        repo.reference(SYNCED_REF, new, true, "replica synchronized")?;
+    }
+
+    Ok(report)
+}
+
+/// Branch of the primary which its `HEAD` points to
+///
+fn primary_branch(remote: &mut Remote) -> SkrdResult<String> {
+    remote.connect(Direction::Fetch)?;
+    let branch = {
+        let heads = remote.list()?;
+        let head = heads
+            .iter()
+            .find(|head| head.name() == "HEAD")
+            .map(|head| head.oid());
+        heads
+            .iter()
+            .filter(|remote_head| remote_head.name().starts_with("refs/heads/"))
+            .find(|remote_head| Some(remote_head.oid()) == head)
+            .map(|remote_head| remote_head.name().to_owned())
+    };
+    remote.disconnect();
+    branch.ok_or(SkrdError::StaticCustom(
+        "The primary has no branch at `HEAD`.",
+    ))
+}
+
+/// Download the crate file of a version from the primary if it is not stored, returns
+/// its size if it is downloaded
+///
+fn download(
+    registry: &Registry,
+    replica: &ReplicaConfig,
+    client: &Client,
+    meta: &CrateMeta,
+) -> SkrdResult<Option<u64>> {
+    let crate_path = registry.crate_path(meta);
+    if registry.storage().exists(&crate_path)? {
+        return Ok(None);
+    }
+
+    let mut response = client.get(&replica.download_url(meta)).send()?;
+    if !response.status().is_success() {
+        return Err(SkrdError::Custom(format!(
+            "Http Response status: {}",
+            response.status().as_u16()
+        )));
+    }
+    let mut content = Vec::new();
+    let len = response.copy_to(&mut content)?;
+
+    if Sha256::digest(&content)[..] != meta.checksum {
+        return Err(SkrdError::Custom(format!(
+            "checksum error: expected={}, actual={}",
+            hex::encode(meta.checksum),
+            hex::encode(Sha256::digest(&content))
+        )));
+    }
+    registry.storage().write(&crate_path, &content)?;
+    info!(
+        "Crate {} ({} bytes) downloaded to {}.",
+        meta, len, crate_path
+    );
+
+    Ok(Some(len))
+}
+
+/// Follow the change feed of the primary, and synchronize the replica when the index of
+/// the primary changes
+///
+/// `log` is called with the report of every synchronization. It never returns unless the
+/// registry is not a replica, or the primary refuses the change feed to it.
+///
+pub fn follow<F: Fn(&SyncReport)>(registry: &Registry, log: F) -> SkrdResult<()> {
+    let replica = replica_config(registry)?;
+    let client = Client::builder()
+        .gzip(false)
+        .timeout(Duration::from_secs(FEED_WAIT * 2))
+        .build()?;
+
+    let mut pending = true;
+    loop {
+        if pending {
+            let result = registry
+                .lock("replica sync")
+                .and_then(|_lock| sync(registry, &client));
+            match result {
+                Ok(report) => {
+                    log(&report);
+                    pending = !report.failed.is_empty();
+                }
+                Err(e) => error!("Synchronization error: {}", e),
+            }
+            if pending {
+                std::thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+        }
+
+        let since = Repository::open(registry.index_path())?
+            .refname_to_id(PRIMARY_REF)
+            .ok();
+        match poll_changes(&client, replica, since) {
+            Ok(Some(page)) => {
+                // the cursor moves on changes of the index other than versions too
+                let moved = match since {
+                    Some(since) => since.to_string() != page.cursor,
+                    None => true,
+                };
+                pending = page.reset || moved;
+                for change in &page.changes {
+                    info!(
+                        "{}-{} is {} on the primary.",
+                        change.name, change.version, change.action
+                    );
+                }
+            }
+            // retrying does not help until the token of the replica or the primary is fixed
+            Ok(None) => {
+                return Err(SkrdError::Custom(format!(
+                    "The change feed of {} is refused, the `token` of the replica should be one of the `feed-tokens` of the primary.",
+                    replica.primary
+                )));
+            }
+            Err(e) => {
+                warn!("Change feed of {} error: {}", replica.primary, e);
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Wait for changes of the primary after commit `since`, returns `None` if the primary
+/// refuses the change feed to the replica
+///
+fn poll_changes(
+    client: &Client,
+    replica: &ReplicaConfig,
+    since: Option<Oid>,
+) -> SkrdResult<Option<ChangePage>> {
+    let mut query = vec![("wait", FEED_WAIT.to_string())];
+    if let Some(since) = since {
+        query.push(("since", since.to_string()));
+    }
+
+    let mut request = client.get(&replica.changes_url()).query(&query);
+    if let Some(token) = &replica.token {
+        request = request.header(reqwest::header::AUTHORIZATION, token.as_str());
+    }
+    let mut response = request.send()?;
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Ok(None),
+        // a primary without `feed-tokens`, or a restarting one behind a proxy
+        StatusCode::NOT_FOUND => Err(SkrdError::StaticCustom(
+            "Http Response status: 404, the change feed may not be enabled on the primary",
+        )),
+        status if !status.is_success() => Err(SkrdError::Custom(format!(
+            "Http Response status: {}",
+            status.as_u16()
+        ))),
+        _ => Ok(Some(response.json()?)),
+    }
+}
+
+fn replica_config(registry: &Registry) -> SkrdResult<&ReplicaConfig> {
+    registry.replica_config().ok_or_else(|| {
+        SkrdError::Custom(format!(
+            "Registry '{}' is not a replica.",
+            registry.config().name()
+        ))
+    })
+}
@@ -1,14 +1,17 @@
 use crate::error::{SkrdError, SkrdResult};
 use crate::registry::{CrateMeta, Mirror, Registry, UrlConfig};
+use crate::storage::{move_legacy_crate_files, Layout};
 use actix_http::http::header::HttpDate;
 use actix_http::ResponseBuilder;
-use actix_web::Responder;
+use actix_web::{HttpResponse, Responder};
 use digest::Digest;
 use git2::build::CheckoutBuilder;
 use git2::Oid;
 use rayon::prelude::*;
 use reqwest::Client;
-use std::fs::{create_dir_all, File, OpenOptions};
+use serde_derive::Serialize;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::time::{Duration, SystemTime};
@@ -76,10 +79,33 @@ pub fn cache_forever(res: impl Responder) -> impl Responder {
         .with_header("Cache-Control", "public, max-age=31536000")
 }
 
+#[derive(Serialize)]
+struct ApiErrors {
+    errors: Vec<ApiErrorDetail>,
+}
+
+#[derive(Serialize)]
+struct ApiErrorDetail {
+    detail: String,
+}
+
+/// Build an error response in the format of the registry web API, which cargo can display
+///
+pub fn api_error<D: Display>(mut builder: ResponseBuilder, detail: D) -> HttpResponse {
+    builder.json(ApiErrors {
+        errors: vec![ApiErrorDetail {
+            detail: detail.to_string(),
+        }],
+    })
+}
+
 /// Write custom url(dl and api) to config.json
 ///
 pub fn write_config_json(registry: &Registry) -> SkrdResult<Option<Oid>> {
     const CONFIG_JSON: &str = "config.json";
+    // `dl` points to the lowercased prefix directories
+    migrate_legacy_crate_files(registry)?;
+
     let path = registry.index_path().join(CONFIG_JSON);
 
     let url_config = UrlConfig::from(registry);
@@ -148,6 +174,50 @@ pub fn write_config_json(registry: &Registry) -> SkrdResult<Option<Oid>> {
     }
 }
 
+/// Move crate files of the prefix layout stored by earlier versions to the lowercased
+/// prefix directories once, see `legacy_crate_path`
+///
+/// `Registry::LOWER_PREFIX_FILE` in the registry root records that the files are moved.
+/// The caller should hold the registry lock.
+///
+pub fn migrate_legacy_crate_files(registry: &Registry) -> SkrdResult<()> {
+    let marker = registry.root().join(Registry::LOWER_PREFIX_FILE);
+    if marker.exists() || registry.virtual_config().is_some() {
+        return Ok(());
+    }
+
+    if registry.layout() == Layout::Prefix {
+        info!(
+            "Start to move crate files of '{}' to lowercased prefix directories ...",
+            registry.config().name()
+        );
+        let report = move_legacy_crate_files(registry)?;
+        info!(
+            "Total: {} moved, {} unchanged, {} missing, {} failed.",
+            report.moved, report.unchanged, report.missing, report.failed
+        );
+
+        if report.failed > 0 {
+            return Err(SkrdError::Custom(format!(
+                "{} crate files are not moved to lowercased prefix directories",
+                report.failed
+            )));
+        }
+    }
+
+    write_lower_prefix_marker(registry.root())
+}
+
+/// Record that the crate files of the registry at `root` are placed by lowercased prefixes
+///
+pub fn write_lower_prefix_marker(root: &Path) -> SkrdResult<()> {
+    std::fs::write(
+        root.join(Registry::LOWER_PREFIX_FILE),
+        "Crate files are placed by lowercased prefix directories.\n",
+    )?;
+    Ok(())
+}
+
 pub fn download_crates(registry: &Registry) -> SkrdResult<()> {
     let mirror = registry.mirror_config().ok_or_else(|| {
         SkrdError::Custom(format!(
@@ -219,14 +289,12 @@ fn download(
     for line in reader.lines() {
         let json = line?;
 
-        let crate_meta = serde_json::from_str::<CrateMeta>(&json)?;
+        let crate_meta = CrateMeta::from_line(&json)?;
 
-        let crate_path = get_crate_path(&crate_meta.name, &crate_meta.version);
-
-        let crate_file_path = registry.crates_path().join(&crate_path);
+        let crate_path = registry.crate_path(&crate_meta);
 
         checked += 1;
-        if !crate_file_path.exists() {
+        if !registry.storage().exists(&crate_path)? {
             let crate_dl_url = format!(
                 "{}/{}/{}/download",
                 mirror.origin_urls.dl, crate_meta.name, crate_meta.version
@@ -264,17 +332,7 @@ fn download(
                         )));
                     }
 
-                    create_dir_all(crate_file_path.parent().ok_or_else(|| {
-                        SkrdError::Custom(format!(
-                            "{} does not have a parent directory.",
-                            crate_file_path.display()
-                        ))
-                    })?)?;
-                    let mut file = OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .open(&crate_file_path)?;
-                    file.write_all(&bytes)?;
+                    registry.storage().write(&crate_path, &bytes)?;
 
                     Ok::<_, SkrdError>(len)
                 });
@@ -284,9 +342,7 @@ fn download(
                     dl_ok += 1;
                     info!(
                         "Crate {} ({} bytes) downloaded to {}.",
-                        crate_meta,
-                        len,
-                        crate_file_path.display()
+                        crate_meta, len, crate_path
                     );
                 }
                 Err(e) => {
@@ -300,20 +356,96 @@ fn download(
     Ok((checked, dl_ok, dl_error))
 }
 
-/// Build crates path
+/// Normalize a crate name for lookup, cargo treats crate names case-insensitively
 ///
-pub fn get_crate_path(name: &str, version: &str) -> String {
-    match name.len() {
-        1 => format!("{}/{}/{}-{}.crate", 1, name, name, version),
-        2 => format!("{}/{}/{}-{}.crate", 2, name, name, version),
-        3 => format!("{}/{}/{}/{}-{}.crate", 3, &name[..1], name, name, version),
+pub fn normalize_crate_name(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+/// Prefix directories of a crate name, which is `{lowerprefix}` of cargo
+///
+/// `1`, `2`, `3/{a}` or `{ab}/{cd}` of the normalized name, split by characters so any
+/// name is safe.
+///
+fn name_prefix(name: &str) -> String {
+    let chars: Vec<char> = normalize_crate_name(name).chars().collect();
+    match chars.len() {
+        0..=2 => chars.len().to_string(),
+        3 => format!("{}/{}", 3, chars[0]),
         _ => format!(
-            "{}/{}/{}/{}-{}.crate",
-            &name[..2],
-            &name[2..4],
-            name,
-            name,
-            version
+            "{}/{}",
+            chars[..2].iter().collect::<String>(),
+            chars[2..4].iter().collect::<String>()
         ),
     }
 }
+
+/// Build crates path, crate files are placed by the lowercased prefix like index files
+///
+pub fn get_crate_path(name: &str, version: &str) -> String {
+    format!("{}/{}/{}-{}.crate", name_prefix(name), name, name, version)
+}
+
+/// Path of a crate file stored by earlier versions, which placed it by the prefix of the
+/// name as given (`{prefix}` of cargo), `None` if it is the same as `get_crate_path`
+///
+pub fn legacy_crate_path(name: &str, version: &str) -> Option<String> {
+    if !name.is_ascii() {
+        return None;
+    }
+    let prefix = match name.len() {
+        0..=2 => name.len().to_string(),
+        3 => format!("{}/{}", 3, &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    };
+    if prefix == name_prefix(name) {
+        return None;
+    }
+
+    Some(format!("{}/{}/{}-{}.crate", prefix, name, name, version))
+}
+
+/// Build index file path, index files are placed by the lowercased crate name
+///
+/// `name` should be a valid crate name, see `is_valid_crate_name`.
+///
+pub fn get_index_path(name: &str) -> String {
+    format!("{}/{}", name_prefix(name), normalize_crate_name(name))
+}
+
+/// Check the shape of a path relative to the index directory: `1/{name}`, `2/{name}`,
+/// `3/{a}/{name}` or `{ab}/{cd}/{name}` with a valid crate name
+///
+/// Prefix directories are not compared with the name, misplaced index files are found by
+/// `index check`.
+///
+pub fn is_index_path(path: &Path) -> bool {
+    let parts: Option<Vec<&str>> = path.iter().map(|s| s.to_str()).collect();
+    let parts = match parts {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let is_prefix = |s: &str, len: usize| {
+        s.len() == len
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+
+    match parts.as_slice() {
+        ["1", name] | ["2", name] => is_valid_crate_name(name),
+        ["3", a, name] => is_prefix(a, 1) && is_valid_crate_name(name),
+        [ab, cd, name] => is_prefix(ab, 2) && is_prefix(cd, 2) && is_valid_crate_name(name),
+        _ => false,
+    }
+}
+
+/// Check a crate name with the rules of cargo: ASCII alphanumeric, `-` or `_`,
+/// starts with a letter and no longer than 64 characters
+///
+pub fn is_valid_crate_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}